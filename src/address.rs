@@ -0,0 +1,417 @@
+use crate::location::FixedCoordinate;
+use crate::place::{OsmId, Place};
+use crate::spatial::SpatialIndex;
+use crate::update::Stores;
+
+/// How far a street may be from a place to be the street of its address,
+/// in degrees.
+pub const STREET_DISTANCE: f64 = 0.006;
+
+/// Nominatim's rank for postcodes, which puts them between the state and
+/// the country.
+const POSTCODE_RANK: u8 = 5;
+
+/// Nominatim's names for the admin levels, used for boundaries that have no
+/// `place` tag.
+const ADMIN_LEVEL_KEYS: [&str; 13] = [
+    "",
+    "continent",
+    "country",
+    "region",
+    "state",
+    "state_district",
+    "county",
+    "municipality",
+    "city",
+    "city_district",
+    "suburb",
+    "neighbourhood",
+    "city_block",
+];
+
+/// One part of an address, like an entry of Nominatim's `addressdetails`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AddressLine {
+    /// What the part is, e.g. `house_number`, `road`, `city` or `country`.
+    pub key: String,
+    pub name: String,
+    pub rank: u8,
+    /// The object the name comes from, `None` for `addr:*` tags.
+    pub osm_id: Option<OsmId>,
+}
+
+/// Nominatim's label for a place in an address: the `place` value for
+/// places, the admin level name for boundaries, `road` for streets and the
+/// class for everything else.
+pub fn address_key(place: &Place, rank: u8) -> String {
+    let (class, value) = (place.category.0.as_str(), place.category.1.as_str());
+    if rank < 26 {
+        if let Some(place_type) = place.tags.get("place") {
+            return place_type.clone();
+        }
+        if (class, value) == ("boundary", "administrative") {
+            return ADMIN_LEVEL_KEYS
+                .get(usize::from(rank / 2))
+                .unwrap_or(&"administrative")
+                .to_string();
+        }
+        return if value == "yes" { class } else { value }.to_string();
+    }
+    if rank < 28 {
+        return "road".to_string();
+    }
+    class.to_string()
+}
+
+/// How far from a place node places can be to have it in their address,
+/// in degrees. From Nominatim's `reverse_place_diameter`.
+pub fn place_node_radius(rank: u8) -> f64 {
+    match rank {
+        0..=4 => 5.0,
+        5..=8 => 1.8,
+        9..=12 => 0.6,
+        13..=17 => 0.16,
+        18 => 0.08,
+        19 => 0.04,
+        _ => 0.02,
+    }
+}
+
+/// The nearest street within `STREET_DISTANCE` of the location that has a
+/// name, and its distance.
+pub fn nearest_street(
+    location: FixedCoordinate,
+    stores: &Stores,
+    index: &SpatialIndex,
+) -> Option<(Place, f64)> {
+    index
+        .within(location.lat_f64(), location.lon_f64(), STREET_DISTANCE)
+        .filter(|entry| matches!(entry.id, OsmId::Way(_)))
+        .filter_map(|entry| stores.place(entry.id))
+        .filter(|place| matches!(place.address_rank(), 26 | 27) && place.name().is_some())
+        .map(|place| {
+            let distance = place.geometry.distance(location);
+            (place, distance)
+        })
+        .filter(|(_, distance)| *distance <= STREET_DISTANCE)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// The named places an address can be made of that contain the location,
+/// or for place nodes are close enough to it, with a rank below `max_rank`.
+/// Sorted from the most detailed to the country, with one place per rank.
+pub fn areas_at(
+    location: FixedCoordinate,
+    max_rank: u8,
+    stores: &Stores,
+    index: &SpatialIndex,
+) -> Vec<(Place, u8)> {
+    let (lat, lon) = (location.lat_f64(), location.lon_f64());
+    let mut areas: Vec<(Place, u8)> = index
+        .at(lat, lon)
+        .filter(|entry| !matches!(entry.id, OsmId::Node(_)))
+        .filter_map(|entry| stores.place(entry.id))
+        .map(|place| {
+            let rank = place.address_rank();
+            (place, rank)
+        })
+        .filter(|(place, rank)| {
+            (1..max_rank).contains(rank)
+                && place.name().is_some()
+                && place.geometry.contains(location)
+        })
+        .collect();
+
+    // Boundaries come first, so a place node is only used for ranks where
+    // there is no boundary.
+    let mut nodes: Vec<(Place, u8, f64)> = index
+        .place_nodes_at(lat, lon)
+        .filter_map(|entry| stores.place(entry.id))
+        .filter_map(|place| {
+            let rank = place.address_rank();
+            let distance = place.geometry.distance(location);
+            if (1..max_rank).contains(&rank)
+                && place.name().is_some()
+                && distance <= place_node_radius(rank)
+            {
+                Some((place, rank, distance))
+            } else {
+                None
+            }
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.2.total_cmp(&b.2));
+    areas.extend(nodes.into_iter().map(|(place, rank, _)| (place, rank)));
+
+    let mut result: Vec<(Place, u8)> = Vec::new();
+    for (place, rank) in areas {
+        // A town node and the boundary of the town often have the same
+        // name at neighbouring ranks.
+        if result
+            .iter()
+            .any(|(p, r)| *r == rank || p.name() == place.name())
+        {
+            continue;
+        }
+        result.push((place, rank));
+    }
+    result.sort_by_key(|(_, rank)| std::cmp::Reverse(*rank));
+    result
+}
+
+/// The address of a place, from the most detailed part to the country: the
+/// place itself, its house number and street, the areas it is in and its
+/// postcode. `addr:street` and `addr:city` are used where the street or a
+/// city-level area is not found in the data. A place without a location
+/// only gets the parts from its own tags.
+pub fn address(place: &Place, stores: &Stores, index: &SpatialIndex) -> Vec<AddressLine> {
+    let rank = place.address_rank();
+    let location = place.geometry.centroid();
+    let tag_line = |key: &str, name: &String, rank: u8| AddressLine {
+        key: key.to_string(),
+        name: name.clone(),
+        rank,
+        osm_id: None,
+    };
+    let place_line = |place: &Place, rank: u8| AddressLine {
+        key: address_key(place, rank),
+        name: place.name().unwrap_or_default().to_string(),
+        rank,
+        osm_id: Some(place.id),
+    };
+
+    let mut lines = Vec::new();
+    if place.name().is_some() {
+        lines.push(place_line(place, rank));
+    }
+    if let Some(housenumber) = place.tags.get("addr:housenumber") {
+        lines.push(tag_line("house_number", housenumber, 30));
+    }
+    if rank > 27 {
+        if let Some(street) = place.tags.get("addr:street") {
+            lines.push(tag_line("road", street, 26));
+        } else if let Some((street, _)) =
+            location.and_then(|location| nearest_street(location, stores, index))
+        {
+            lines.push(place_line(&street, street.address_rank()));
+        }
+    }
+
+    if let Some(location) = location {
+        let max_rank = if rank == 0 { 26 } else { rank.min(26) };
+        for (area, area_rank) in areas_at(location, max_rank, stores, index) {
+            lines.push(place_line(&area, area_rank));
+        }
+    }
+
+    if let Some(city) = place.tags.get("addr:city") {
+        if !lines.iter().any(|l| (16..=20).contains(&l.rank)) {
+            lines.push(tag_line("city", city, 16));
+        }
+    }
+    if let Some(postcode) = place.tags.get("addr:postcode") {
+        lines.push(tag_line("postcode", postcode, POSTCODE_RANK));
+    }
+    lines.sort_by_key(|line| std::cmp::Reverse(line.rank));
+    lines
+}
+
+/// A place found by a lookup, with its address, in the shape of a result
+/// of Nominatim's `jsonv2` format.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlaceResult {
+    pub osm_type: &'static str,
+    pub osm_id: i64,
+    pub lat: f64,
+    pub lon: f64,
+    pub category: String,
+    #[serde(rename = "type")]
+    pub place_type: String,
+    pub place_rank: u8,
+    pub name: Option<String>,
+    pub display_name: String,
+    pub address: Vec<AddressLine>,
+}
+
+impl PlaceResult {
+    /// `None` for a place without a location to report.
+    pub fn new(place: &Place, address: Vec<AddressLine>) -> Option<Self> {
+        let centroid = place.geometry.centroid()?;
+        let mut names: Vec<&str> = Vec::new();
+        for line in address.iter() {
+            if names.last() != Some(&line.name.as_str()) {
+                names.push(&line.name);
+            }
+        }
+        let display_name = if names.is_empty() {
+            place.category.1.clone()
+        } else {
+            names.join(", ")
+        };
+        Some(PlaceResult {
+            osm_type: match place.id {
+                OsmId::Node(_) => "node",
+                OsmId::Way(_) => "way",
+                OsmId::Relation(_) => "relation",
+            },
+            osm_id: place.id.id(),
+            lat: centroid.lat_f64(),
+            lon: centroid.lon_f64(),
+            category: place.category.0.clone(),
+            place_type: place.category.1.clone(),
+            place_rank: place.address_rank(),
+            name: place.name().map(String::from),
+            display_name,
+            address,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::update::tests::Data;
+
+    /// The country Testland around the city Testdorf, both boundaries, with
+    /// Hauptstrasse running east to west through the middle of the city, a
+    /// café just north of the street and a house further north.
+    pub const TOWN: &str = r#"<create>
+        <node id="1" lat="46.0" lon="7.0"/>
+        <node id="2" lat="46.0" lon="9.0"/>
+        <node id="3" lat="48.0" lon="9.0"/>
+        <node id="4" lat="48.0" lon="7.0"/>
+        <node id="5" lat="46.9" lon="7.9"/>
+        <node id="6" lat="46.9" lon="8.1"/>
+        <node id="7" lat="47.1" lon="8.1"/>
+        <node id="8" lat="47.1" lon="7.9"/>
+        <node id="9" lat="47.0" lon="7.95"/>
+        <node id="10" lat="47.0" lon="8.05"/>
+        <node id="40" lat="47.0005" lon="8.0">
+          <tag k="amenity" v="cafe"/><tag k="name" v="Café Zentral"/>
+        </node>
+        <node id="41" lat="47.002" lon="8.01">
+          <tag k="addr:housenumber" v="10"/><tag k="addr:postcode" v="8000"/>
+        </node>
+        <way id="20"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><nd ref="1"/></way>
+        <way id="21"><nd ref="5"/><nd ref="6"/><nd ref="7"/><nd ref="8"/><nd ref="5"/></way>
+        <way id="30">
+          <nd ref="9"/><nd ref="10"/>
+          <tag k="highway" v="residential"/><tag k="name" v="Hauptstrasse"/>
+        </way>
+        <relation id="100">
+          <member type="way" ref="20" role="outer"/>
+          <tag k="type" v="boundary"/><tag k="boundary" v="administrative"/>
+          <tag k="admin_level" v="2"/><tag k="name" v="Testland"/>
+        </relation>
+        <relation id="101">
+          <member type="way" ref="21" role="outer"/>
+          <tag k="type" v="boundary"/><tag k="boundary" v="administrative"/>
+          <tag k="admin_level" v="8"/><tag k="name" v="Testdorf"/>
+        </relation>
+    </create>"#;
+
+    pub fn town() -> Data {
+        let data = Data::default();
+        data.apply(TOWN);
+        data
+    }
+
+    fn at(lat: f64, lon: f64) -> FixedCoordinate {
+        FixedCoordinate::new(lat, lon).unwrap()
+    }
+
+    fn area_names(location: FixedCoordinate, max_rank: u8, data: &Data) -> Vec<(String, u8)> {
+        areas_at(location, max_rank, &data.stores(), &data.spatial_index())
+            .into_iter()
+            .map(|(place, rank)| (place.name().unwrap().to_string(), rank))
+            .collect()
+    }
+
+    #[test]
+    fn points_are_in_the_boundaries_around_them() {
+        let data = town();
+        assert_eq!(
+            area_names(at(47.05, 8.05), 26, &data),
+            vec![("Testdorf".to_string(), 16), ("Testland".to_string(), 4)]
+        );
+        // Outside of the city, but in the country.
+        assert_eq!(
+            area_names(at(47.5, 8.5), 26, &data),
+            vec![("Testland".to_string(), 4)]
+        );
+        // Only ranks below the maximum.
+        assert_eq!(
+            area_names(at(47.05, 8.05), 16, &data),
+            vec![("Testland".to_string(), 4)]
+        );
+        assert!(area_names(at(45.0, 8.0), 26, &data).is_empty());
+    }
+
+    #[test]
+    fn place_nodes_stand_in_for_missing_boundaries() {
+        let data = town();
+        data.apply(
+            r#"<create>
+                <node id="50" lat="47.5" lon="8.5"><tag k="place" v="village"/><tag k="name" v="Weiler"/></node>
+                <node id="51" lat="47.05" lon="8.05"><tag k="place" v="town"/><tag k="name" v="Testdorf"/></node>
+            </create>"#,
+        );
+        assert_eq!(
+            area_names(at(47.51, 8.51), 26, &data),
+            vec![("Weiler".to_string(), 19), ("Testland".to_string(), 4)]
+        );
+        // The town node has the name of the city boundary it is in.
+        assert_eq!(
+            area_names(at(47.05, 8.05), 26, &data),
+            vec![("Testdorf".to_string(), 16), ("Testland".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn address_lines_go_from_the_house_to_the_country() {
+        let data = town();
+        let stores = data.stores();
+        let index = data.spatial_index();
+        let house = stores.place(OsmId::Node(41)).unwrap();
+        let lines: Vec<(String, String, u8)> = address(&house, &stores, &index)
+            .into_iter()
+            .map(|line| (line.key, line.name, line.rank))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("house_number".to_string(), "10".to_string(), 30),
+                ("road".to_string(), "Hauptstrasse".to_string(), 26),
+                ("city".to_string(), "Testdorf".to_string(), 16),
+                ("postcode".to_string(), "8000".to_string(), 5),
+                ("country".to_string(), "Testland".to_string(), 4),
+            ]
+        );
+
+        let result = PlaceResult::new(&house, address(&house, &stores, &index)).unwrap();
+        assert_eq!(
+            result.display_name,
+            "10, Hauptstrasse, Testdorf, 8000, Testland"
+        );
+    }
+
+    #[test]
+    fn address_tags_fill_in_what_is_not_found() {
+        let data = town();
+        data.apply(
+            r#"<create>
+                <node id="60" lat="47.5" lon="8.5">
+                  <tag k="shop" v="bakery"/><tag k="name" v="Beck"/>
+                  <tag k="addr:street" v="Feldweg"/><tag k="addr:city" v="Weiler"/>
+                </node>
+            </create>"#,
+        );
+        let stores = data.stores();
+        let shop = stores.place(OsmId::Node(60)).unwrap();
+        let names: Vec<String> = address(&shop, &stores, &data.spatial_index())
+            .into_iter()
+            .map(|line| line.name)
+            .collect();
+        assert_eq!(names, vec!["Beck", "Feldweg", "Weiler", "Testland"]);
+    }
+}
@@ -2,25 +2,22 @@ extern crate num_cpus;
 extern crate osm_pbf_iter;
 
 use std::env::args;
-use std::fs::File;
-use std::io::{BufReader, Seek, SeekFrom};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::thread;
 use std::time::Instant;
 
 use osm_pbf_iter::*;
 
+#[allow(dead_code)]
+mod input;
+use input::OsmReader;
+
 type Stats = [u64; 3];
 
 fn blobs_worker(req_rx: Receiver<Blob>, res_tx: SyncSender<Stats>) {
     let mut stats = [0; 3];
 
-    loop {
-        let blob = match req_rx.recv() {
-            Ok(blob) => blob,
-            Err(_) => break,
-        };
-
+    while let Ok(blob) = req_rx.recv() {
         let data = blob.into_data();
         let primitive_block = PrimitiveBlock::parse(&data);
         for primitive in primitive_block.primitives() {
@@ -50,8 +47,7 @@ fn main() {
         }
 
         println!("Open {}", arg);
-        let f = File::open(&arg).unwrap();
-        let mut reader = BlobReader::new(BufReader::new(f));
+        let mut reader = OsmReader::open(&arg).unwrap();
         let start = Instant::now();
 
         let mut w = 0;
@@ -61,6 +57,10 @@ fn main() {
 
             req_tx.send(blob).unwrap();
         }
+        if let Some(e) = reader.take_error() {
+            eprintln!("Could not read {}: {}.", arg, e);
+            std::process::exit(1);
+        }
 
         let mut stats = [0; 3];
         for (req_tx, res_rx) in workers.into_iter() {
@@ -74,19 +74,14 @@ fn main() {
         let stop = Instant::now();
         let duration = stop.duration_since(start);
         let duration = duration.as_secs() as f64 + (duration.subsec_nanos() as f64 / 1e9);
-        let mut f = reader.into_inner();
-        match f.seek(SeekFrom::Current(0)) {
-            Ok(pos) => {
-                let rate = pos as f64 / 1024f64 / 1024f64 / duration;
-                println!(
-                    "Processed {} MB in {:.2} seconds ({:.2} MB/s)",
-                    pos / 1024 / 1024,
-                    duration,
-                    rate
-                );
-            }
-            Err(_) => (),
-        }
+        let pos = reader.position();
+        let rate = pos as f64 / 1024f64 / 1024f64 / duration;
+        println!(
+            "Processed {} MB in {:.2} seconds ({:.2} MB/s)",
+            pos / 1024 / 1024,
+            duration,
+            rate
+        );
 
         println!(
             "{} - {} nodes, {} ways, {} relations",
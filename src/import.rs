@@ -1,30 +1,92 @@
 #![allow(unused_imports)]
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate serde_derive;
 extern crate atomic_counter;
 extern crate dashmap;
 extern crate num_cpus;
 extern crate osm_pbf_iter;
+extern crate serde_json;
 
 use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::env::args;
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::{BufReader, Seek, SeekFrom};
 use std::iter::FromIterator;
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use atomic_counter::{AtomicCounter, RelaxedCounter};
 use dashmap::DashMap;
 use osm_pbf_iter::*;
+use serde::de::DeserializeOwned;
+
+mod address;
+
+#[allow(dead_code)]
+mod input;
+use input::{ExtractHeader, OsmReader};
+
+#[allow(dead_code)]
+mod location;
+use location::{str_to_coord, FixedCoordinate};
+
+mod merge;
+use merge::{is_duplicate, VersionDB};
+
+mod multipolygon;
 
 mod node;
 use node::{process_node, NodeCoordDB, NodeTags, NodeTagsDB};
 
+mod node_store;
+use node_store::{FlatNodeStore, NodeLocationStore};
+
+mod persist;
+use persist::FileStamp;
+
+#[allow(dead_code)]
+mod place;
+use place::OsmId;
+
+mod relation;
+use relation::{process_relation, RelationDB};
+
+mod replication;
+use replication::{ReplicationSource, ReplicationState, Replicator};
+
+mod report;
+use report::{ChangeReport, FileReport, ImportReport, IndexReport, PhaseReport, StoreSizes};
+
+mod reverse;
+use reverse::reverse;
+
+mod reverse_index;
+use reverse_index::ReverseIndex;
+
+mod search;
+use search::{search, structured_search, SearchIndex, StructuredQuery};
+
+mod spatial;
+use spatial::SpatialIndex;
+
+mod style;
+use style::Style;
+
+mod tokenizer;
+
+mod update;
+use update::{apply_change, Stores};
+
 mod way;
 use way::{process_way, DebugWay, LineString, RoadsDB, WayDB, WayProcessingError};
 
@@ -45,363 +107,1013 @@ lazy_static! {
 }
 
 lazy_static! {
-    pub static ref POLYGON_KEYS: HashSet<&'static str> = HashSet::from_iter(vec![
-        "aeroway",
-        "abandoned:aeroway",
-        "abandoned:amenity",
-        "abandoned:building",
-        "abandoned:landuse",
-        "abandoned:power",
-        "area:highway",
-        "amenity",
-        "building",
-        "harbour",
-        "historic",
-        "landuse",
-        "leisure",
-        "man_made",
-        "military",
-        "natural",
-        "office",
-        "place",
-        "power",
-        "public_transport",
-        "shop",
-        "sport",
-        "tourism",
-        "water",
-        "waterway",
-        "wetland",
-    ]);
+    pub static ref REVERSE_INDEX: Arc<ReverseIndex> = Arc::from(ReverseIndex::new());
 }
 
 lazy_static! {
-    pub static ref GENERIC_KEYS: HashSet<&'static str> = HashSet::from_iter(vec![
-        "access",
-        "addr:housename",
-        "addr:housenumber",
-        "addr:interpolation",
-        "admin_level",
-        "aerialway",
-        "aeroway",
-        "amenity",
-        "area",
-        "barrier",
-        "bicycle",
-        "boundary",
-        "brand",
-        "bridge",
-        "building",
-        "capital",
-        "construction",
-        "covered",
-        "culvert",
-        "cutting",
-        "denomination",
-        "disused",
-        "ele",
-        "embarkment",
-        "foot",
-        "generation:source",
-        "harbour",
-        "highway",
-        "historic",
-        "hours",
-        "intermittent",
-        "junction",
-        "landuse",
-        "layer",
-        "leisure",
-        "lock",
-        "man_made",
-        "military",
-        "motor_car",
-        "name",
-        "natural",
-        "office",
-        "oneway",
-        "operator",
-        "place",
-        "population",
-        "power",
-        "power_source",
-        "public_transport",
-        "railway",
-        "ref",
-        "religion",
-        "route",
-        "service",
-        "shop",
-        "sport",
-        "surface",
-        "toll",
-        "tourism",
-        "tower:type",
-        "tracktype",
-        "tunnel",
-        "type",
-        "water",
-        "waterway",
-        "wetland",
-        "width",
-        "wood",
-    ]);
+    pub static ref RELATION_DB: Arc<RelationDB> = Arc::from(DashMap::with_capacity(5_000_000));
 }
 
 lazy_static! {
-    pub static ref DELETE_TAGS: HashSet<&'static str> =
-        HashSet::from_iter(vec!["FIXME", "note", "source",]);
+    pub static ref SPATIAL_INDEX: Arc<RwLock<SpatialIndex>> =
+        Arc::new(RwLock::new(SpatialIndex::default()));
 }
 
 lazy_static! {
-    pub static ref WAYS_GOOD_COUNT: RelaxedCounter = RelaxedCounter::new(0);
+    pub static ref SEARCH_INDEX: Arc<RwLock<SearchIndex>> =
+        Arc::new(RwLock::new(SearchIndex::default()));
 }
 
 lazy_static! {
-    pub static ref WAYS_ERROR_COUNT: RelaxedCounter = RelaxedCounter::new(0);
+    pub static ref VERSION_DB: Arc<VersionDB> = Arc::from(DashMap::new());
 }
 
-fn collapse_this_comment() {
-    // OsmType  Tag          DataType     Flags
-    // node,way   access       text         linear
-    // node,way   addr:housename      text  linear
-    // node,way   addr:housenumber    text  linear
-    // node,way   addr:interpolation  text  linear
-    // node,way   admin_level  text         linear
-    // node,way   aerialway    text         linear
-    // node,way   aeroway      text         polygon
-    // node,way   amenity      text         polygon
-    // node,way   area         text         polygon # hard coded support for area=1/yes => polygon is in osm2pgsql
-    // node,way   barrier      text         linear
-    // node,way   bicycle      text         linear
-    // node,way   brand        text         linear
-    // node,way   bridge       text         linear
-    // node,way   boundary     text         linear
-    // node,way   building     text         polygon
-    // node       capital      text         linear
-    // node,way   construction text         linear
-    // node,way   covered      text         linear
-    // node,way   culvert      text         linear
-    // node,way   cutting      text         linear
-    // node,way   denomination text         linear
-    // node,way   disused      text         linear
-    // node       ele          text         linear
-    // node,way   embankment   text         linear
-    // node,way   foot         text         linear
-    // node,way   generator:source    text  linear
-    // node,way   harbour      text         polygon
-    // node,way   highway      text         linear
-    // node,way   historic     text         polygon
-    // node,way   horse        text         linear
-    // node,way   intermittent text         linear
-    // node,way   junction     text         linear
-    // node,way   landuse      text         polygon
-    // node,way   layer        text         linear
-    // node,way   leisure      text         polygon
-    // node,way   lock         text         linear
-    // node,way   man_made     text         polygon
-    // node,way   military     text         polygon
-    // node,way   motorcar     text         linear
-    // node,way   name         text         linear
-    // node,way   natural      text         polygon  # natural=coastline tags are discarded by a hard coded rule in osm2pgsql
-    // node,way   office       text         polygon
-    // node,way   oneway       text         linear
-    // node,way   operator     text         linear
-    // node,way   place        text         polygon
-    // node,way   population   text         linear
-    // node,way   power        text         polygon
-    // node,way   power_source text         linear
-    // node,way   public_transport text     polygon
-    // node,way   railway      text         linear
-    // node,way   ref          text         linear
-    // node,way   religion     text         linear
-    // node,way   route        text         linear
-    // node,way   service      text         linear
-    // node,way   shop         text         polygon
-    // node,way   sport        text         polygon
-    // node,way   surface      text         linear
-    // node,way   toll         text         linear
-    // node,way   tourism      text         polygon
-    // node,way   tower:type   text         linear
-    // way        tracktype    text         linear
-    // node,way   tunnel       text         linear
-    // node,way   water        text         polygon
-    // node,way   waterway     text         polygon
-    // node,way   wetland      text         polygon
-    // node,way   width        text         linear
-    // node,way   wood         text         linear
-    // node,way   z_order      int4         linear # This is calculated during import
-    // way        way_area     real         linear # This is calculated during import
-
-    // pub fn is_area<'a>(way: &Way<'a>) -> bool {
-
-    // }
+/// The import runs in three phases over the same file. Every node has to be
+/// in the `NODE_COORD_DB` before any way is built from its refs, and every
+/// way has to be in the `WAY_DB` before relation areas are assembled.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Phase {
+    Nodes = 0,
+    Ways = 1,
+    Relations = 2,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
-pub struct DebugStats {
-    pub num_tags_objects: u64,
-    pub total_size: u64,
-    pub min_size: u64,
-    pub max_size: u64,
-}
+/// Which primitive types a block contains, indexed by `Phase`. Recorded
+/// during the node phase so the later phases only decode the blocks they need.
+pub type BlockKinds = [bool; 3];
 
-pub fn blobs_worker<'a>(req_rx: Receiver<Blob>, stats: Sender<DebugStats>) {
-    let mut early_termination = false;
-    let mut debug_stats = DebugStats {
-        num_tags_objects: 0,
-        total_size: 0,
-        min_size: 100,
-        max_size: 0,
-    };
+pub fn blobs_worker(
+    phase: Phase,
+    style: Arc<Style>,
+    node_store: Arc<dyn NodeLocationStore>,
+    versions: Option<Arc<VersionDB>>,
+    req_rx: Arc<Mutex<Receiver<(usize, Blob)>>>,
+    stats: Sender<(PhaseReport, Vec<(usize, BlockKinds)>)>,
+) {
+    let mut report = PhaseReport::default();
+    let mut block_kinds = Vec::new();
     loop {
-        if early_termination {
-            drop(req_rx);
-            stats
-                .send(debug_stats)
-                .expect("stats Reciever disconnected.");
-            println!("Worker exit.");
-            break;
-        }
-
-        let blob = match req_rx.try_recv() {
-            Ok(blob) => blob,
-            Err(TryRecvError::Empty) => continue,
-            Err(TryRecvError::Disconnected) => {
-                println!("Worker sender disconneced.");
+        // The lock is only held while waiting for the next blob, and is
+        // released before the blob is decoded.
+        let msg = req_rx.lock().unwrap().recv();
+        let (index, blob) = match msg {
+            Ok(msg) => msg,
+            Err(_) => {
+                stats
+                    .send((report, block_kinds))
+                    .expect("stats Reciever disconnected.");
                 break;
             }
         };
 
+        let mut kinds: BlockKinds = [false; 3];
         let data = blob.into_data();
         let primitive_block = PrimitiveBlock::parse(&data);
         for primitive in primitive_block.primitives() {
             match primitive {
                 Primitive::Node(n) => {
-                    if let Some(n) = process_node(&n, &NODE_COORD_DB, &GENERIC_KEYS, &NODE_TAGS_DB)
-                    {
-                        debug_stats.total_size += n;
-                        debug_stats.num_tags_objects += 1;
-                        debug_stats.min_size = min(debug_stats.min_size, n);
-                        debug_stats.max_size = max(debug_stats.max_size, n);
+                    kinds[Phase::Nodes as usize] = true;
+                    if phase != Phase::Nodes {
+                        continue;
+                    }
+                    if is_duplicate(versions.as_deref(), OsmId::Node(n.id as i64), &n.info) {
+                        report.duplicates += 1;
+                        continue;
+                    }
+                    if versions.is_some() {
+                        // An older version may have had tags this one lacks.
+                        NODE_TAGS_DB.remove(&(n.id as i64));
+                    }
+                    match process_node(&n, node_store.as_ref(), &style, &NODE_TAGS_DB) {
+                        Ok(size) => {
+                            report.processed += 1;
+                            if let Some(size) = size {
+                                report.tags.add(size);
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Could not store node {}: {:?}.", n.id as i64, e);
+                            report.error(e.kind());
+                        }
                     }
                 }
                 Primitive::Way(w) => {
-                    if let Err(e) =
-                        process_way(&w, &GENERIC_KEYS, &NODE_COORD_DB, &ROADS_DB, &WAY_DB)
+                    kinds[Phase::Ways as usize] = true;
+                    if phase != Phase::Ways {
+                        continue;
+                    }
+                    if is_duplicate(versions.as_deref(), OsmId::Way(w.id as i64), &w.info) {
+                        report.duplicates += 1;
+                        continue;
+                    }
+                    if versions.is_some() {
+                        // An older version may have been a road, or had
+                        // other nodes.
+                        ROADS_DB.remove(&(w.id as i64));
+                        if let Some(old) = WAY_DB.get(&(w.id as i64)) {
+                            REVERSE_INDEX.remove_way(old.id(), old.node_ids());
+                        }
+                    }
+                    if let Err(e) = process_way(&w, &style, node_store.as_ref(), &ROADS_DB, &WAY_DB)
                     {
-                        eprintln!("Could not process {:?}, because {:?} could not be found in the NODE_COORDS_DB.", w, e);
-                        WAYS_ERROR_COUNT.inc();
+                        eprintln!("Could not process way {}: {:?}.", w.id as i64, e);
+                        report.error(e.kind());
                     } else {
-                        WAYS_GOOD_COUNT.inc();
+                        REVERSE_INDEX.add_way(w.id as i64, w.refs());
+                        report.processed += 1;
                     }
                 }
-                _ => {
-                    early_termination = true;
-                    break;
+                Primitive::Relation(r) => {
+                    kinds[Phase::Relations as usize] = true;
+                    if phase != Phase::Relations {
+                        continue;
+                    }
+                    if is_duplicate(versions.as_deref(), OsmId::Relation(r.id as i64), &r.info) {
+                        report.duplicates += 1;
+                        continue;
+                    }
+                    if versions.is_some() {
+                        if let Some(old) = RELATION_DB.get(&(r.id as i64)) {
+                            REVERSE_INDEX.remove_relation(old.id(), old.member_ids());
+                        }
+                    }
+                    REVERSE_INDEX.add_relation(
+                        r.id as i64,
+                        r.members()
+                            .map(|(_, id, t)| OsmId::from_member(&t, id as i64)),
+                    );
+                    if let Err(e) = process_relation(&r, &style, &WAY_DB, &RELATION_DB) {
+                        eprintln!(
+                            "Could not assemble the area of relation {}: {:?}.",
+                            r.id as i64, e
+                        );
+                        report.error(e.kind());
+                    } else {
+                        report.processed += 1;
+                    }
                 }
             }
         }
+
+        if phase == Phase::Nodes {
+            block_kinds.push((index, kinds));
+        }
     }
 }
 
-fn process() {
-    let cpus = num_cpus::get();
+/// Runs one phase over the whole file and returns once every worker has
+/// finished, which is the barrier between phases. All workers take blobs
+/// from one queue holding at most two blobs per worker, so an idle worker
+/// picks up the next blob instead of waiting behind a slow one, and the
+/// reader blocks when the workers fall behind. Blocks that are known not
+/// to contain primitives of this phase are read but not sent to the workers.
+/// Returns the merged counts of the workers.
+fn run_phase(
+    path: &str,
+    phase: Phase,
+    style: &Arc<Style>,
+    node_store: &Arc<dyn NodeLocationStore>,
+    versions: Option<&Arc<VersionDB>>,
+    block_kinds: &mut Vec<BlockKinds>,
+    cpus: usize,
+) -> PhaseReport {
+    let start = Instant::now();
+    let (stats_snd, stats_rec) = channel();
+    let (req_tx, req_rx) = sync_channel(2 * cpus);
+    let req_rx = Arc::new(Mutex::new(req_rx));
 
-    for arg in args().skip(1) {
-        let mut workers = Vec::with_capacity(cpus);
-        let (stats_snd, stats_rec) = channel();
+    for _ in 0..cpus {
+        let req_rx = req_rx.clone();
+        let stats_snd = stats_snd.clone();
+        let style = style.clone();
+        let node_store = node_store.clone();
+        let versions = versions.cloned();
 
-        for _ in 0..cpus {
-            let (wkr_snd, wkr_rec) = channel();
-            let stats_snd = stats_snd.clone();
+        thread::spawn(move || {
+            blobs_worker(phase, style, node_store, versions, req_rx, stats_snd);
+        });
+    }
 
-            workers.push(wkr_snd);
+    let mut reader = OsmReader::open(path).unwrap();
 
-            thread::spawn(move || {
-                blobs_worker(wkr_rec, stats_snd);
-            });
+    for (index, blob) in (&mut reader).enumerate() {
+        if phase != Phase::Nodes
+            && !block_kinds
+                .get(index)
+                .is_some_and(|kinds| kinds[phase as usize])
+        {
+            continue;
         }
 
-        println!("Open {}", arg);
-        let f = File::open(&arg).unwrap();
-        let mut reader = BlobReader::new(BufReader::new(f));
-        let start = Instant::now();
+        if let Err(e) = req_tx.send((index, blob)) {
+            eprintln!("Error sending blob to worker: {:?}.", e);
+            break;
+        };
+    }
+    // A truncated or corrupt file must not pass for a complete import.
+    if let Some(e) = reader.take_error() {
+        eprintln!("Could not read {}: {}.", path, e);
+        exit(1);
+    }
 
-        let mut w = 0;
-        for blob in &mut reader {
-            let req_tx = &workers[w];
-            w = (w + 1) % cpus;
+    drop(req_tx);
+    drop(stats_snd);
 
-            if let Err(e) = req_tx.send(blob) {
-                eprintln!("Error sending blob to worker: {:?}.", e);
-                break;
-            };
+    let mut report = PhaseReport {
+        phase: format!("{:?}", phase),
+        ..PhaseReport::default()
+    };
+    for (worker_report, kinds) in stats_rec.iter() {
+        report.merge(&worker_report);
+        for (index, k) in kinds {
+            if block_kinds.len() <= index {
+                block_kinds.resize(index + 1, [false; 3]);
+            }
+            block_kinds[index] = k;
         }
+    }
+
+    report.bytes = reader.position();
+    report.seconds = start.elapsed().as_secs_f64();
+    report.stores = StoreSizes {
+        node_coords: node_store.len(),
+        node_tags: NODE_TAGS_DB.len(),
+        ways: WAY_DB.len(),
+        roads: ROADS_DB.len(),
+        relations: RELATION_DB.len(),
+    };
+    report
+}
+
+#[derive(Debug)]
+pub enum NodeStoreKind {
+    Memory,
+    Flat(PathBuf),
+}
 
-        let mut debug_stats = DebugStats {
-            num_tags_objects: 0,
-            total_size: 0,
-            min_size: 100,
-            max_size: 0,
+/// Command line options of the `import` binary. Every argument that is not
+/// a `--flag` is an input file.
+#[derive(Debug)]
+pub struct ImportOptions {
+    pub node_store: NodeStoreKind,
+    pub max_node_id: u64,
+    /// Open the existing flat node store and keep the locations in it,
+    /// instead of starting with an empty one.
+    pub keep_node_store: bool,
+    /// An osm2pgsql style file, the built-in default style if not given.
+    pub style: Option<PathBuf>,
+    /// Where to write the `ImportReport` as JSON.
+    pub report: Option<PathBuf>,
+    /// Merge the files into one store, keeping the newest version of
+    /// objects that are in more than one file.
+    pub merge: bool,
+    pub files: Vec<String>,
+    /// osmChange files applied after the import, in order.
+    pub updates: Vec<PathBuf>,
+    /// A replication directory whose diffs are applied after the updates.
+    pub replication: Option<ReplicationSource>,
+    /// The diff the input files are at, for files without a replication
+    /// sequence in their header.
+    pub replication_start: Option<u64>,
+    /// Keep polling the replication source for new diffs.
+    pub follow: bool,
+    /// Where to write the spatial index, and read it from on the next run
+    /// over the same data, see `IndexFingerprint`. Defaults to the flat node store path with
+    /// `.rtree` appended, with the memory node store it is only kept in
+    /// memory.
+    pub spatial_index: Option<PathBuf>,
+    /// Where to write the search index, and read it from on the next run
+    /// over the same data. Defaults to the flat node store path with `.terms`
+    /// appended, like the spatial index.
+    pub search_index: Option<PathBuf>,
+    /// Locations to look up once the import is done, see `--zoom`.
+    pub reverse: Vec<FixedCoordinate>,
+    /// The detail of reverse lookups, as in Nominatim: 18 for buildings,
+    /// 10 for cities, 3 for countries.
+    pub zoom: u8,
+    /// Free-text queries to look up once the import is done.
+    pub search: Vec<String>,
+    /// Structured queries to look up, as URL query parameters.
+    pub structured: Vec<StructuredQuery>,
+}
+
+impl ImportOptions {
+    pub fn from_args() -> Result<Self, String> {
+        let mut options = ImportOptions {
+            node_store: NodeStoreKind::Memory,
+            // Room for the ~12 billion node ids of the planet and some growth.
+            max_node_id: 16_000_000_000,
+            keep_node_store: false,
+            style: None,
+            report: None,
+            merge: false,
+            files: Vec::new(),
+            updates: Vec::new(),
+            replication: None,
+            replication_start: None,
+            follow: false,
+            spatial_index: None,
+            search_index: None,
+            reverse: Vec::new(),
+            zoom: 18,
+            search: Vec::new(),
+            structured: Vec::new(),
         };
 
-        for (n, worker) in workers.iter().enumerate() {
-            println!("Dropping worker: {}. {:?}.", n, worker);
-            let stats = stats_rec.recv().unwrap();
-            debug_stats.num_tags_objects += stats.num_tags_objects;
-            debug_stats.total_size += stats.total_size;
-            debug_stats.min_size = min(debug_stats.min_size, stats.min_size);
-            debug_stats.max_size = max(debug_stats.max_size, stats.max_size);
-            drop(worker);
+        for arg in args().skip(1) {
+            if let Some(store) = arg.strip_prefix("--node-store=") {
+                options.node_store = match store {
+                    "memory" => NodeStoreKind::Memory,
+                    _ => match store.strip_prefix("flat:") {
+                        Some(path) => NodeStoreKind::Flat(PathBuf::from(path)),
+                        None => return Err(format!("Unknown node store: {}.", store)),
+                    },
+                };
+            } else if let Some(n) = arg.strip_prefix("--max-node-id=") {
+                options.max_node_id = n
+                    .parse()
+                    .map_err(|e| format!("Invalid --max-node-id {}: {}.", n, e))?;
+            } else if let Some(path) = arg.strip_prefix("--style=") {
+                options.style = Some(PathBuf::from(path));
+            } else if let Some(path) = arg.strip_prefix("--report=") {
+                options.report = Some(PathBuf::from(path));
+            } else if let Some(path) = arg.strip_prefix("--update=") {
+                options.updates.push(PathBuf::from(path));
+            } else if let Some(source) = arg.strip_prefix("--replication=") {
+                options.replication = Some(ReplicationSource::parse(source)?);
+            } else if let Some(n) = arg.strip_prefix("--replication-start=") {
+                options.replication_start = Some(
+                    n.parse()
+                        .map_err(|e| format!("Invalid --replication-start {}: {}.", n, e))?,
+                );
+            } else if let Some(path) = arg.strip_prefix("--spatial-index=") {
+                options.spatial_index = Some(PathBuf::from(path));
+            } else if let Some(path) = arg.strip_prefix("--search-index=") {
+                options.search_index = Some(PathBuf::from(path));
+            } else if let Some(coord) = arg.strip_prefix("--reverse=") {
+                options
+                    .reverse
+                    .push(str_to_coord(coord).map_err(|e| format!("Invalid --reverse: {}.", e))?);
+            } else if let Some(zoom) = arg.strip_prefix("--zoom=") {
+                options.zoom = zoom
+                    .parse()
+                    .map_err(|e| format!("Invalid --zoom {}: {}.", zoom, e))?;
+            } else if let Some(query) = arg.strip_prefix("--search=") {
+                options.search.push(query.to_string());
+            } else if let Some(params) = arg.strip_prefix("--structured=") {
+                options.structured.push(StructuredQuery::parse(params)?);
+            } else if arg == "--keep-node-store" {
+                options.keep_node_store = true;
+            } else if arg == "--follow" {
+                options.follow = true;
+            } else if arg == "--merge" {
+                options.merge = true;
+            } else if arg.starts_with("--") {
+                return Err(format!("Unknown option: {}.", arg));
+            } else {
+                options.files.push(arg);
+            }
+        }
+
+        if options.keep_node_store {
+            if let NodeStoreKind::Memory = options.node_store {
+                return Err("--keep-node-store needs a flat node store.".to_string());
+            }
+        }
+
+        if let (None, NodeStoreKind::Flat(path)) = (&options.spatial_index, &options.node_store) {
+            let mut index = path.clone().into_os_string();
+            index.push(".rtree");
+            options.spatial_index = Some(PathBuf::from(index));
+        }
+        if let (None, NodeStoreKind::Flat(path)) = (&options.search_index, &options.node_store) {
+            let mut index = path.clone().into_os_string();
+            index.push(".terms");
+            options.search_index = Some(PathBuf::from(index));
+        }
+
+        Ok(options)
+    }
+}
+
+fn print_change_report(change_report: &ChangeReport) {
+    println!(
+        "{}: {} created, {} modified, {} deleted, {} ways and {} relations rebuilt in {:.2} seconds, {} errors {:?}.",
+        change_report.file,
+        change_report.created,
+        change_report.modified,
+        change_report.deleted,
+        change_report.rebuilt_ways,
+        change_report.rebuilt_relations,
+        change_report.seconds,
+        change_report.errors.values().sum::<u64>(),
+        change_report.errors
+    );
+}
+
+/// The diff the input files are at: `--replication-start` or else the
+/// sequence in the headers of the files, which have to agree.
+fn replication_start(options: &ImportOptions) -> Result<ReplicationState, String> {
+    if let Some(sequence) = options.replication_start {
+        return Ok(ReplicationState::from_header(sequence, None));
+    }
+    let mut start: Option<ReplicationState> = None;
+    for file in options.files.iter() {
+        let header =
+            ExtractHeader::read(file).map_err(|e| format!("Could not read {}: {}.", file, e))?;
+        let sequence = header.replication_sequence.ok_or_else(|| {
+            format!(
+                "{} has no replication sequence, --replication needs --replication-start.",
+                file
+            )
+        })?;
+        match &start {
+            Some(state) if state.sequence != sequence => {
+                return Err(format!(
+                    "The input files are at different diffs ({} and {}), --replication needs --replication-start.",
+                    state.sequence, sequence
+                ))
+            }
+            Some(_) => (),
+            None => {
+                start = Some(ReplicationState::from_header(
+                    sequence,
+                    header.replication_timestamp,
+                ))
+            }
+        }
+    }
+    start.ok_or_else(|| "--replication needs input files or --replication-start.".to_string())
+}
+
+/// What the indexes were built from: the input and update files, the
+/// style, this program, whether the files were merged and how the search
+/// terms were normalized. An index is only
+/// read again by a run with the same fingerprint, otherwise its entries and
+/// their envelopes may belong to other data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexFingerprint {
+    files: Vec<FileStamp>,
+    updates: Vec<FileStamp>,
+    style: Option<FileStamp>,
+    program: FileStamp,
+    merge: bool,
+    normalization: String,
+}
+
+impl IndexFingerprint {
+    pub fn new(options: &ImportOptions) -> io::Result<Self> {
+        Ok(IndexFingerprint {
+            files: options
+                .files
+                .iter()
+                .map(FileStamp::new)
+                .collect::<io::Result<_>>()?,
+            updates: options
+                .updates
+                .iter()
+                .map(FileStamp::new)
+                .collect::<io::Result<_>>()?,
+            style: options.style.as_ref().map(FileStamp::new).transpose()?,
+            program: FileStamp::new(env::current_exe()?)?,
+            merge: options.merge,
+            normalization: tokenizer::normalization(),
+        })
+    }
+}
+
+/// Reads an index that an earlier run with the same `fingerprint` wrote to
+/// `path`, `len` counts its entries for the report.
+fn load_index<T: DeserializeOwned>(
+    path: Option<&PathBuf>,
+    fingerprint: Option<&IndexFingerprint>,
+    name: &str,
+    len: fn(&T) -> usize,
+) -> Option<(T, IndexReport)> {
+    let (path, fingerprint) = (path?, fingerprint?);
+    if !path.exists() {
+        return None;
+    }
+    let start = Instant::now();
+    let index = match persist::read_matching(path, &Some(fingerprint.clone())) {
+        Ok(Some(index)) => index,
+        Ok(None) => {
+            println!(
+                "The {} {} was built from other data, building it again.",
+                name,
+                path.display()
+            );
+            return None;
         }
+        Err(e) => {
+            eprintln!(
+                "Could not read the {} {}, building it again: {}.",
+                name,
+                path.display(),
+                e
+            );
+            return None;
+        }
+    };
+    let index_report = IndexReport {
+        seconds: start.elapsed().as_secs_f64(),
+        entries: len(&index),
+    };
+    println!(
+        "Loaded the {} {} with {} entries in {:.2} seconds.",
+        name,
+        path.display(),
+        index_report.entries,
+        index_report.seconds
+    );
+    Some((index, index_report))
+}
 
-        let total_size_mb = debug_stats.total_size as f64 / 1_000_000.0;
+/// Bulk loads the `SPATIAL_INDEX` from the stores and writes it to the
+/// configured path with the `fingerprint` of the data.
+fn build_spatial_index(
+    options: &ImportOptions,
+    fingerprint: Option<&IndexFingerprint>,
+    stores: &Stores,
+) -> IndexReport {
+    let start = Instant::now();
+    let index = SpatialIndex::build(
+        stores.node_store,
+        stores.node_tags,
+        stores.ways,
+        stores.relations,
+    );
+    if let Some(path) = &options.spatial_index {
+        if let Err(e) = persist::write_with_header(path, &fingerprint, &index) {
+            eprintln!(
+                "Could not write the spatial index {}: {}.",
+                path.display(),
+                e
+            );
+        }
+    }
+    let index_report = IndexReport {
+        seconds: start.elapsed().as_secs_f64(),
+        entries: index.len(),
+    };
+    println!(
+        "Built the spatial index with {} entries in {:.2} seconds.",
+        index_report.entries, index_report.seconds
+    );
+    *SPATIAL_INDEX.write().unwrap() = index;
+    index_report
+}
 
-        let stop = Instant::now();
-        let duration = stop.duration_since(start);
-        let secs = duration.as_secs_f64();
-        let mut f = reader.into_inner();
-        match f.seek(SeekFrom::Current(0)) {
-            Ok(pos) => {
-                let rate = pos as f64 / 1024f64 / 1024f64 / secs;
+/// Collects the names and `addr:*` values of all places into the
+/// `SEARCH_INDEX` and writes it to the configured path with the
+/// `fingerprint` of the data.
+fn build_search_index(
+    options: &ImportOptions,
+    fingerprint: Option<&IndexFingerprint>,
+    stores: &Stores,
+) -> IndexReport {
+    let start = Instant::now();
+    let index = SearchIndex::build(stores);
+    if let Some(path) = &options.search_index {
+        if let Err(e) = persist::write_with_header(path, &fingerprint, &index) {
+            eprintln!(
+                "Could not write the search index {}: {}.",
+                path.display(),
+                e
+            );
+        }
+    }
+    let index_report = IndexReport {
+        seconds: start.elapsed().as_secs_f64(),
+        entries: index.len(),
+    };
+    println!(
+        "Built the search index with {} terms in {:.2} seconds.",
+        index_report.entries, index_report.seconds
+    );
+    *SEARCH_INDEX.write().unwrap() = index;
+    index_report
+}
+
+fn process(options: &ImportOptions) -> ImportReport {
+    let cpus = num_cpus::get();
+
+    let style = Arc::new(match &options.style {
+        Some(path) => match Style::from_file(path) {
+            Ok(style) => style,
+            Err(e) => {
+                eprintln!("Could not load style {}: {}.", path.display(), e);
+                exit(1);
+            }
+        },
+        None => Style::default(),
+    });
+
+    let mut flat_store = None;
+    let node_store: Arc<dyn NodeLocationStore> = match &options.node_store {
+        NodeStoreKind::Memory => NODE_COORD_DB.clone(),
+        NodeStoreKind::Flat(path) => {
+            let store = if options.keep_node_store {
+                FlatNodeStore::open(path, options.max_node_id)
+            } else {
+                FlatNodeStore::create(path, options.max_node_id)
+            };
+            match store {
+                Ok(store) => {
+                    let store = Arc::new(store);
+                    flat_store = Some(store.clone());
+                    store
+                }
+                Err(e) => {
+                    eprintln!("Could not open node store {}: {}.", path.display(), e);
+                    exit(1);
+                }
+            }
+        }
+    };
+
+    // With replication the data changes without the input files, so the
+    // indexes are always built then, and written without a fingerprint.
+    let fingerprint = if options.replication.is_some() {
+        None
+    } else {
+        match IndexFingerprint::new(options) {
+            Ok(fingerprint) => Some(fingerprint),
+            Err(e) => {
+                eprintln!(
+                    "Could not fingerprint the input, building the indexes: {}.",
+                    e
+                );
+                None
+            }
+        }
+    };
+
+    // Checked before the import writes anything.
+    let loaded_spatial_index = load_index(
+        options.spatial_index.as_ref(),
+        fingerprint.as_ref(),
+        "spatial index",
+        SpatialIndex::len,
+    )
+    .map(|(index, index_report)| {
+        *SPATIAL_INDEX.write().unwrap() = index;
+        index_report
+    });
+    let loaded_search_index = load_index(
+        options.search_index.as_ref(),
+        fingerprint.as_ref(),
+        "search index",
+        SearchIndex::len,
+    )
+    .map(|(index, index_report)| {
+        *SEARCH_INDEX.write().unwrap() = index;
+        index_report
+    });
+
+    // Set up before the import, so missing or conflicting sequences and
+    // a node store of older data are reported before hours of work.
+    let mut replicator = match &options.replication {
+        Some(source) => {
+            let replicator = replication_start(options).and_then(|imported| {
                 println!(
-                    "Processed {} MB raw osm.pbf data in {:.2} seconds ({:.2} MB/s).",
-                    pos / 1024 / 1024,
-                    secs,
-                    rate
+                    "The input files are at diff {} ({}).",
+                    imported.sequence,
+                    imported.timestamp.as_deref().unwrap_or("no timestamp")
                 );
+                Replicator::start(source.clone(), node_store.as_ref(), &imported)
+                    .map_err(|e| format!("Could not replicate from {:?}: {}.", source, e))
+            });
+            match replicator {
+                Ok(replicator) => Some(replicator),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1);
+                }
             }
-            Err(_) => (),
         }
-        println!("node_coords entry count: {}.", NODE_COORD_DB.len());
-        println!("Num node tags objects: {}.", debug_stats.num_tags_objects);
-        println!("Total size of all tags: {} MB.", total_size_mb);
-        println!("Good ways count: {}.", WAYS_GOOD_COUNT.get());
-        println!("Error ways count: {}.", WAYS_ERROR_COUNT.get());
-        println!("Ways db len: {}.", WAY_DB.len());
-        println!("Roads db len: {}.", ROADS_DB.len());
-        println!("min_size: {}.", debug_stats.min_size);
-        println!("max_size: {}.", debug_stats.max_size);
-        println!(
-            "Avg obj processing rate: {:.3} objs/s.",
-            debug_stats.num_tags_objects as f64 / secs
+        None => None,
+    };
+
+    let start = Instant::now();
+    let mut report = ImportReport::default();
+    let phases = [Phase::Nodes, Phase::Ways, Phase::Relations];
+
+    // Files are imported one after another. When merging, each phase runs
+    // over all files before the next one starts, so ways are built from the
+    // newest version of their nodes whichever file it came from.
+    let steps: Vec<(usize, Phase)> = if options.merge {
+        phases
+            .iter()
+            .flat_map(|phase| (0..options.files.len()).map(move |i| (i, *phase)))
+            .collect()
+    } else {
+        (0..options.files.len())
+            .flat_map(|i| phases.iter().map(move |phase| (i, *phase)))
+            .collect()
+    };
+    let versions = if options.merge {
+        Some(VERSION_DB.clone())
+    } else {
+        None
+    };
+
+    report.files = options
+        .files
+        .iter()
+        .map(|file| FileReport {
+            file: file.clone(),
+            ..FileReport::default()
+        })
+        .collect();
+    let mut block_kinds: Vec<Vec<BlockKinds>> = vec![Vec::new(); options.files.len()];
+
+    for (i, phase) in steps {
+        let file = &options.files[i];
+        let phase_report = run_phase(
+            file,
+            phase,
+            &style,
+            &node_store,
+            versions.as_ref(),
+            &mut block_kinds[i],
+            cpus,
         );
         println!(
-            "Avg obj processing rate: {:.6} MB/s.",
-            total_size_mb as f64 / secs
+            "{}: phase {} done in {:.2} seconds: {} processed, {} duplicates, {} errors {:?}.",
+            file,
+            phase_report.phase,
+            phase_report.seconds,
+            phase_report.processed,
+            phase_report.duplicates,
+            phase_report.error_count(),
+            phase_report.errors
         );
+
+        let file_report = &mut report.files[i];
+        file_report.bytes = phase_report.bytes;
+        file_report.seconds += phase_report.seconds;
+        file_report.phases.push(phase_report);
+    }
+
+    for file_report in report.files.iter() {
         println!(
-            "Avg processing time per obj: {:.3} us.",
-            duration.as_micros() as f64 / debug_stats.num_tags_objects as f64
+            "Processed {} MB raw osm.pbf data from {} in {:.2} seconds ({:.2} MB/s).",
+            file_report.bytes / 1024 / 1024,
+            file_report.file,
+            file_report.seconds,
+            file_report.bytes as f64 / 1024f64 / 1024f64 / file_report.seconds
         );
-        println!(
-            "Avg processing time per MB of objs: {:.6} ms.",
-            duration.as_millis() as f64 / total_size_mb
+    }
+    report.combine();
+
+    let stores = Stores {
+        node_store: node_store.as_ref(),
+        node_tags: &NODE_TAGS_DB,
+        ways: &WAY_DB,
+        roads: &ROADS_DB,
+        relations: &RELATION_DB,
+        reverse_index: &REVERSE_INDEX,
+    };
+    for path in options.updates.iter() {
+        match apply_change(path, &style, &stores) {
+            Ok(change_report) => {
+                print_change_report(&change_report);
+                report.changes.push(change_report);
+            }
+            Err(e) => {
+                eprintln!("Could not apply {}: {}.", path.display(), e);
+                exit(1);
+            }
+        }
+    }
+
+    if let Some(replicator) = &mut replicator {
+        loop {
+            match replicator.catch_up(&style, &stores) {
+                Ok(change_reports) => {
+                    let applied = !change_reports.is_empty();
+                    for change_report in change_reports {
+                        print_change_report(&change_report);
+                        report.changes.push(change_report);
+                    }
+                    if applied {
+                        println!("The data is at diff {}.", replicator.applied());
+                    }
+                    // While following there is no end of the import to wait
+                    // for, so the index is rebuilt whenever diffs were applied.
+                    if options.follow && (applied || report.spatial_index.is_none()) {
+                        report.spatial_index =
+                            Some(build_spatial_index(options, fingerprint.as_ref(), &stores));
+                        report.search_index =
+                            Some(build_search_index(options, fingerprint.as_ref(), &stores));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Could not replicate from {:?}: {}.", replicator.source(), e);
+                    if !options.follow {
+                        exit(1);
+                    }
+                }
+            }
+            if !options.follow {
+                break;
+            }
+            // Minutely diffs, so there is nothing new to get before then.
+            thread::sleep(Duration::from_secs(60));
+        }
+    }
+
+    if report.spatial_index.is_none() {
+        report.spatial_index = Some(
+            loaded_spatial_index
+                .unwrap_or_else(|| build_spatial_index(options, fingerprint.as_ref(), &stores)),
+        );
+        report.search_index = Some(
+            loaded_search_index
+                .unwrap_or_else(|| build_search_index(options, fingerprint.as_ref(), &stores)),
         );
     }
+
+    // One line of JSON per lookup, `null` if nothing was found.
+    let index = SPATIAL_INDEX.read().unwrap();
+    for coord in options.reverse.iter() {
+        match reverse(
+            coord.lat_f64(),
+            coord.lon_f64(),
+            options.zoom,
+            &stores,
+            &index,
+        ) {
+            Ok(result) => println!("{}", serde_json::to_string(&result).unwrap()),
+            Err(e) => eprintln!("Could not look up {:?}: {}.", coord, e),
+        }
+    }
+    // And one JSON array of results per query.
+    let search_index = SEARCH_INDEX.read().unwrap();
+    for query in options.search.iter() {
+        let results = search(query, &stores, &index, &search_index);
+        println!("{}", serde_json::to_string(&results).unwrap());
+    }
+    for query in options.structured.iter() {
+        let results = structured_search(query, &stores, &index, &search_index);
+        println!("{}", serde_json::to_string(&results).unwrap());
+    }
+
+    if let Some(store) = flat_store {
+        if let Err(e) = store.flush() {
+            eprintln!("Could not flush the node store: {}.", e);
+        }
+    }
+
+    report.seconds = start.elapsed().as_secs_f64();
+    report
 }
 
 fn main() {
-    process();
+    match ImportOptions::from_args() {
+        Ok(options) => {
+            let report = process(&options);
+            if let Some(path) = &options.report {
+                if let Err(e) = report.write_json(path) {
+                    eprintln!("Could not write report {}: {}.", path.display(), e);
+                    exit(1);
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "Usage: import [--node-store=memory|flat:PATH] [--max-node-id=N] [--keep-node-store] [--style=FILE] [--report=FILE] [--merge] [--update=FILE.osc]... [--replication=DIR|URL [--replication-start=N] [--follow]] [--spatial-index=FILE] [--search-index=FILE] [--reverse=LAT,LON]... [--zoom=N] [--search=QUERY]... [--structured=street=..&city=..]... FILE..."
+            );
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{encode_block, Element};
+
+    fn node_store() -> Arc<dyn NodeLocationStore> {
+        Arc::new(NodeCoordDB::default())
+    }
+
+    /// The relation before its way and the way before its nodes, each in
+    /// its own block. The ids do not clash with the other tests, which share
+    /// the global stores.
+    const BACKWARDS: &str = r#"<osm version="0.6">
+        <relation id="1001">
+          <member type="way" ref="1001" role="outer"/>
+          <tag k="type" v="multipolygon"/><tag k="landuse" v="forest"/>
+        </relation>
+        <way id="1001">
+          <nd ref="1001"/><nd ref="1002"/><nd ref="1003"/><nd ref="1001"/>
+        </way>
+        <node id="1001" lat="47.0" lon="8.0"/>
+        <node id="1002" lat="47.0" lon="8.1"/>
+        <node id="1003" lat="47.1" lon="8.1"/>
+    </osm>"#;
+
+    #[test]
+    fn phases_run_in_order_whatever_the_order_of_the_file() {
+        let path = env::temp_dir().join(format!(
+            "nominatim_rs-test-{}-phases.osm",
+            std::process::id()
+        ));
+        fs::write(&path, BACKWARDS).unwrap();
+        let path = path.to_str().unwrap();
+        let (style, node_store) = (Arc::new(Style::default()), node_store());
+        let mut block_kinds = Vec::new();
+
+        let nodes = run_phase(
+            path,
+            Phase::Nodes,
+            &style,
+            &node_store,
+            None,
+            &mut block_kinds,
+            2,
+        );
+        assert_eq!(nodes.processed, 3);
+        assert_eq!(
+            block_kinds,
+            vec![
+                [false, false, true],
+                [false, true, false],
+                [true, false, false]
+            ]
+        );
+        assert!(WAY_DB.get(&1001).is_none());
+
+        let ways = run_phase(
+            path,
+            Phase::Ways,
+            &style,
+            &node_store,
+            None,
+            &mut block_kinds,
+            2,
+        );
+        assert_eq!(ways.processed, 1);
+        assert_eq!(WAY_DB.get(&1001).unwrap().coords_shape().coords().len(), 4);
+        assert!(RELATION_DB.get(&1001).is_none());
+
+        let relations = run_phase(
+            path,
+            Phase::Relations,
+            &style,
+            &node_store,
+            None,
+            &mut block_kinds,
+            2,
+        );
+        fs::remove_file(path).unwrap();
+        assert_eq!(relations.processed, 1);
+        assert_eq!(relations.error_count(), 0);
+        assert!(RELATION_DB.get(&1001).unwrap().area().is_some());
+    }
+
+    #[test]
+    fn workers_share_one_queue() {
+        let (style, node_store) = (Arc::new(Style::default()), node_store());
+        let (stats_snd, stats_rec) = channel();
+        let (req_tx, req_rx) = sync_channel(2);
+        let req_rx = Arc::new(Mutex::new(req_rx));
+        for _ in 0..3 {
+            let (style, node_store) = (style.clone(), node_store.clone());
+            let (req_rx, stats_snd) = (req_rx.clone(), stats_snd.clone());
+            thread::spawn(move || {
+                blobs_worker(Phase::Nodes, style, node_store, None, req_rx, stats_snd)
+            });
+        }
+        drop(stats_snd);
+
+        // More blobs than the queue holds, so sending blocks until the
+        // workers take them.
+        for index in 0..20 {
+            let node = Element::Node {
+                id: 2000 + index as i64,
+                lat: 47.0,
+                lon: 8.0,
+                info: Default::default(),
+                tags: Vec::new(),
+            };
+            req_tx
+                .send((index, Blob::Raw(encode_block(&[node]))))
+                .unwrap();
+        }
+        drop(req_tx);
+
+        let (mut processed, mut indexes) = (0, Vec::new());
+        for (report, kinds) in stats_rec.iter() {
+            processed += report.processed;
+            indexes.extend(kinds.iter().map(|(index, _)| *index));
+        }
+        indexes.sort_unstable();
+        assert_eq!(processed, 20);
+        assert_eq!(indexes, (0..20).collect::<Vec<_>>());
+        assert_eq!(node_store.len(), 20);
+    }
 }
@@ -0,0 +1,750 @@
+//! Reading OSM files in every format the binaries accept: PBF, XML (`.osm`
+//! and osmChange `.osc`, plain or compressed with gzip or bzip2) and O5M.
+//! PBF files are read blob by blob. Elements of the other formats are
+//! encoded into PBF blocks, so workers handle every format the same way.
+
+pub mod encode;
+mod o5m;
+mod pbf;
+mod xml;
+
+pub use encode::{encode_block, Element};
+pub use o5m::O5mParser;
+pub use pbf::PbfReader;
+pub use xml::XmlParser;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use osm_pbf_iter::Blob;
+use protobuf_iter::MessageIter;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Number of elements encoded into one block, about what PBF writers put
+/// into a block.
+const BLOCK_SIZE: usize = 8000;
+
+#[derive(Debug)]
+pub enum InputError {
+    Io(io::Error),
+    Parse(String),
+}
+
+impl Display for InputError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            InputError::Io(e) => write!(f, "{}", e),
+            InputError::Parse(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for InputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InputError::Io(e) => Some(e),
+            InputError::Parse(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for InputError {
+    fn from(e: io::Error) -> Self {
+        InputError::Io(e)
+    }
+}
+
+/// What an osmChange file does with an element. Elements of other files
+/// are created.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Pbf,
+    Xml,
+    O5m,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+}
+
+/// The format and compression given by the extension of `path`, for
+/// example `.osm.bz2` or `.o5m`, `None` for unknown extensions.
+pub fn format_from_extension(path: &Path) -> Option<(Format, Compression)> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    let (name, compression) = if let Some(name) = name.strip_suffix(".gz") {
+        (name, Compression::Gzip)
+    } else if let Some(name) = name.strip_suffix(".bz2") {
+        (name, Compression::Bzip2)
+    } else {
+        (name.as_str(), Compression::None)
+    };
+    let format = match name.rsplit('.').next()? {
+        "pbf" => Format::Pbf,
+        "osm" | "osc" | "xml" => Format::Xml,
+        "o5m" | "o5c" => Format::O5m,
+        _ => return None,
+    };
+    Some((format, compression))
+}
+
+fn sniff_compression(head: &[u8]) -> Compression {
+    if head.starts_with(&[0x1f, 0x8b]) {
+        Compression::Gzip
+    } else if head.starts_with(b"BZh") {
+        Compression::Bzip2
+    } else {
+        Compression::None
+    }
+}
+
+fn sniff_format(head: &[u8]) -> Format {
+    let text = head.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(head);
+    match text.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'<') => Format::Xml,
+        _ if head.starts_with(&[0xff, 0xe0]) => Format::O5m,
+        _ => Format::Pbf,
+    }
+}
+
+/// Counts the bytes read from the file, before decompression.
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Opens the file at `path` and decompresses it. The format is taken from
+/// the extension, or sniffed from the content if the extension is unknown.
+fn open_file(
+    path: &Path,
+    position: &Rc<Cell<u64>>,
+) -> io::Result<(Format, Compression, Box<dyn BufRead>)> {
+    let mut file = BufReader::new(CountingReader {
+        inner: File::open(path)?,
+        count: position.clone(),
+    });
+
+    let from_extension = format_from_extension(path);
+    let compression = match from_extension {
+        Some((_, compression)) => compression,
+        None => sniff_compression(file.fill_buf()?),
+    };
+    let mut reader: Box<dyn BufRead> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Compression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(file))),
+    };
+    let format = match from_extension {
+        Some((format, _)) => format,
+        None => sniff_format(reader.fill_buf()?),
+    };
+    Ok((format, compression, reader))
+}
+
+enum Parser {
+    Xml(XmlParser<Box<dyn BufRead>>),
+    O5m(O5mParser<Box<dyn BufRead>>),
+}
+
+impl Parser {
+    fn new(format: Format, reader: Box<dyn BufRead>) -> Self {
+        match format {
+            Format::O5m => Parser::O5m(O5mParser::new(reader)),
+            _ => Parser::Xml(XmlParser::new(reader)),
+        }
+    }
+
+    fn next_element(&mut self) -> Result<Option<(Action, Element)>, InputError> {
+        match self {
+            Parser::Xml(p) => p.next_element(),
+            Parser::O5m(p) => p.next_element(),
+        }
+    }
+}
+
+enum Blobs {
+    Pbf(PbfReader<Box<dyn BufRead>>),
+    Elements {
+        parser: Box<Parser>,
+        /// The first element of the next block.
+        pending: Option<Element>,
+        done: bool,
+    },
+}
+
+/// Iterates over the blobs of an OSM file in any supported format, like
+/// osm_pbf_iter's `BlobReader` does for PBF files. Deletions in change files are skipped.
+/// A read or parse error ends the iteration, `take_error` tells it apart
+/// from the end of the file.
+pub struct OsmReader {
+    blobs: Blobs,
+    format: Format,
+    compression: Compression,
+    position: Rc<Cell<u64>>,
+    error: Option<InputError>,
+}
+
+impl OsmReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let position = Rc::new(Cell::new(0));
+        let (format, compression, reader) = open_file(path.as_ref(), &position)?;
+        let blobs = match format {
+            Format::Pbf => Blobs::Pbf(PbfReader::new(reader)),
+            _ => Blobs::Elements {
+                parser: Box::new(Parser::new(format, reader)),
+                pending: None,
+                done: false,
+            },
+        };
+
+        Ok(OsmReader {
+            blobs,
+            format,
+            compression,
+            position,
+            error: None,
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Number of bytes read from the file so far.
+    pub fn position(&self) -> u64 {
+        self.position.get()
+    }
+
+    /// The error that ended the iteration before the end of the file.
+    pub fn take_error(&mut self) -> Option<InputError> {
+        self.error.take()
+    }
+}
+
+impl Iterator for OsmReader {
+    type Item = Blob;
+
+    fn next(&mut self) -> Option<Blob> {
+        let (parser, pending, done) = match &mut self.blobs {
+            Blobs::Pbf(reader) => {
+                return reader.next_blob().unwrap_or_else(|e| {
+                    self.error = Some(e);
+                    None
+                })
+            }
+            Blobs::Elements {
+                parser,
+                pending,
+                done,
+            } => (parser, pending, done),
+        };
+
+        let mut block: Vec<Element> = pending.take().into_iter().collect();
+        while !*done && block.len() < BLOCK_SIZE {
+            match parser.next_element() {
+                Ok(Some((Action::Delete, _))) => (),
+                Ok(Some((_, element))) => {
+                    if block
+                        .first()
+                        .is_some_and(|first| !first.same_kind(&element))
+                    {
+                        *pending = Some(element);
+                        break;
+                    }
+                    block.push(element);
+                }
+                Ok(None) => *done = true,
+                Err(e) => {
+                    self.error = Some(e);
+                    *done = true;
+                }
+            }
+        }
+
+        if block.is_empty() {
+            None
+        } else {
+            Some(Blob::Raw(encode_block(&block)))
+        }
+    }
+}
+
+/// Iterates over the elements of an XML or O5M file together with their
+/// action, which is what applying a change file needs.
+pub struct ElementReader {
+    parser: Box<Parser>,
+    done: bool,
+}
+
+impl ElementReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let (format, _compression, reader) = open_file(path.as_ref(), &Rc::new(Cell::new(0)))?;
+        if format == Format::Pbf {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PBF files cannot hold changes",
+            ));
+        }
+        Ok(ElementReader {
+            parser: Box::new(Parser::new(format, reader)),
+            done: false,
+        })
+    }
+}
+
+impl Iterator for ElementReader {
+    type Item = Result<(Action, Element), InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.parser.next_element();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result.transpose()
+    }
+}
+
+/// The replication state a PBF extract was made at, from the
+/// `osmosis_replication_*` fields of its header. Other formats have none.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractHeader {
+    pub replication_sequence: Option<u64>,
+    /// Seconds since the epoch.
+    pub replication_timestamp: Option<i64>,
+}
+
+impl ExtractHeader {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, InputError> {
+        let (format, _compression, reader) = open_file(path.as_ref(), &Rc::new(Cell::new(0)))?;
+        if format != Format::Pbf {
+            return Ok(ExtractHeader::default());
+        }
+
+        // The header block is optional.
+        let blob = match PbfReader::new(reader).next_raw()? {
+            Some(raw) if raw.kind == b"OSMHeader" => raw.blob,
+            _ => return Ok(ExtractHeader::default()),
+        };
+        let mut data = Vec::new();
+        for m in MessageIter::new(&blob) {
+            match m.tag {
+                1 => data = m.value.get_data().to_vec(),
+                3 => {
+                    ZlibDecoder::new(m.value.get_data()).read_to_end(&mut data)?;
+                }
+                _ => (),
+            }
+        }
+
+        let mut header = ExtractHeader::default();
+        for m in MessageIter::new(&data) {
+            match m.tag {
+                // Plain int64, `i64::from` would undo a zigzag encoding.
+                32 => header.replication_timestamp = Some(u64::from(m.value) as i64),
+                33 => header.replication_sequence = Some(u64::from(m.value)),
+                _ => (),
+            }
+        }
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode::{ElementInfo, Member};
+    use super::*;
+    use osm_pbf_iter::{Primitive, PrimitiveBlock, RelationMemberType};
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="47.5" lon="-8.25" version="2" timestamp="2020-01-31T12:00:00Z" changeset="5" uid="7" user="me">
+  <tag k="name" v="Zürich"/>
+ </node>
+ <node id="-2" lat="-33.9" lon="151.2"/>
+ <way id="10">
+  <nd ref="1"/>
+  <nd ref="-2"/>
+  <tag k="highway" v="primary"/>
+ </way>
+ <relation id="20">
+  <member type="way" ref="10" role="outer"/>
+  <member type="node" ref="1" role=""/>
+  <tag k="type" v="multipolygon"/>
+ </relation>
+</osm>
+"#;
+
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn signed(out: &mut Vec<u8>, value: i64) {
+        varint(out, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn string_pair(out: &mut Vec<u8>, first: &[u8], second: &str) {
+        out.push(0);
+        out.extend_from_slice(first);
+        out.push(0);
+        out.extend_from_slice(second.as_bytes());
+        out.push(0);
+    }
+
+    fn dataset(out: &mut Vec<u8>, kind: u8, body: &[u8]) {
+        out.push(kind);
+        varint(out, body.len() as u64);
+        out.extend_from_slice(body);
+    }
+
+    /// The same data as `XML`, written the way o5m writers do.
+    fn o5m() -> Vec<u8> {
+        let mut file = vec![0xff];
+        dataset(&mut file, 0xe0, b"o5m2");
+
+        let mut node = Vec::new();
+        signed(&mut node, 1);
+        varint(&mut node, 2);
+        signed(&mut node, 1_580_472_000);
+        signed(&mut node, 5);
+        string_pair(&mut node, &[7], "me");
+        signed(&mut node, -82_500_000);
+        signed(&mut node, 475_000_000);
+        string_pair(&mut node, b"name", "Zürich");
+        dataset(&mut file, 0x10, &node);
+
+        let mut node = Vec::new();
+        signed(&mut node, -3);
+        varint(&mut node, 0);
+        signed(&mut node, 1_512_000_000 + 82_500_000);
+        signed(&mut node, -339_000_000 - 475_000_000);
+        dataset(&mut file, 0x10, &node);
+
+        file.push(0xff);
+        let mut way = Vec::new();
+        signed(&mut way, 10);
+        varint(&mut way, 0);
+        let mut refs = Vec::new();
+        signed(&mut refs, 1);
+        signed(&mut refs, -3);
+        varint(&mut way, refs.len() as u64);
+        way.extend(refs);
+        string_pair(&mut way, b"highway", "primary");
+        dataset(&mut file, 0x11, &way);
+
+        file.push(0xff);
+        let mut relation = Vec::new();
+        signed(&mut relation, 20);
+        varint(&mut relation, 0);
+        let mut members = Vec::new();
+        signed(&mut members, 10);
+        members.extend_from_slice(b"\x001outer\0");
+        signed(&mut members, 1);
+        members.extend_from_slice(b"\x000\0");
+        varint(&mut relation, members.len() as u64);
+        relation.extend(members);
+        string_pair(&mut relation, b"type", "multipolygon");
+        dataset(&mut file, 0x12, &relation);
+
+        file.push(0xfe);
+        file
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn expected() -> Vec<Element> {
+        vec![
+            Element::Node {
+                id: 1,
+                lat: 47.5,
+                lon: -8.25,
+                info: ElementInfo {
+                    version: Some(2),
+                    timestamp: Some(1_580_472_000),
+                    changeset: Some(5),
+                    uid: Some(7),
+                    user: Some("me".to_string()),
+                },
+                tags: tags(&[("name", "Zürich")]),
+            },
+            Element::Node {
+                id: -2,
+                lat: -33.9,
+                lon: 151.2,
+                info: ElementInfo::default(),
+                tags: Vec::new(),
+            },
+            Element::Way {
+                id: 10,
+                refs: vec![1, -2],
+                info: ElementInfo::default(),
+                tags: tags(&[("highway", "primary")]),
+            },
+            Element::Relation {
+                id: 20,
+                members: vec![
+                    Member {
+                        id: 10,
+                        member_type: RelationMemberType::Way,
+                        role: "outer".to_string(),
+                    },
+                    Member {
+                        id: 1,
+                        member_type: RelationMemberType::Node,
+                        role: String::new(),
+                    },
+                ],
+                info: ElementInfo::default(),
+                tags: tags(&[("type", "multipolygon")]),
+            },
+        ]
+    }
+
+    fn collect(parser: &mut Parser) -> Result<Vec<Element>, InputError> {
+        let mut elements = Vec::new();
+        while let Some((action, element)) = parser.next_element()? {
+            assert_eq!(action, Action::Create);
+            elements.push(element);
+        }
+        Ok(elements)
+    }
+
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("nominatim_rs-test-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn xml_and_o5m_read_the_same_elements() {
+        let xml: Box<dyn BufRead> = Box::new(Cursor::new(XML.as_bytes().to_vec()));
+        let o5m: Box<dyn BufRead> = Box::new(Cursor::new(o5m()));
+        let from_xml = collect(&mut Parser::new(Format::Xml, xml)).unwrap();
+        let from_o5m = collect(&mut Parser::new(Format::O5m, o5m)).unwrap();
+        assert_eq!(from_xml, expected());
+        assert_eq!(from_o5m, expected());
+    }
+
+    #[test]
+    fn elements_survive_pbf_encoding() {
+        let elements = expected();
+        let data = encode_block(&elements[..2]);
+        let block = PrimitiveBlock::parse(&data);
+        let nodes: Vec<_> = block
+            .primitives()
+            .map(|p| match p {
+                Primitive::Node(n) => (
+                    n.id,
+                    (n.lat * 1e7).round() as i64,
+                    (n.lon * 1e7).round() as i64,
+                    n.tags,
+                ),
+                p => panic!("unexpected {:?}", p),
+            })
+            .collect();
+        assert_eq!(
+            nodes[0],
+            (1, 475_000_000, -82_500_000, vec![("name", "Zürich")])
+        );
+        assert_eq!(
+            nodes[1],
+            (-2i64 as u64, -339_000_000, 1_512_000_000, vec![])
+        );
+        assert_eq!(nodes.len(), 2);
+
+        let data = encode_block(&elements[2..3]);
+        let block = PrimitiveBlock::parse(&data);
+        match block.primitives().next() {
+            Some(Primitive::Way(w)) => {
+                assert_eq!(w.id, 10);
+                assert_eq!(w.refs().collect::<Vec<i64>>(), vec![1, -2]);
+                assert_eq!(w.tags().collect::<Vec<_>>(), vec![("highway", "primary")]);
+            }
+            p => panic!("unexpected {:?}", p),
+        }
+
+        let data = encode_block(&elements[3..]);
+        let block = PrimitiveBlock::parse(&data);
+        match block.primitives().next() {
+            Some(Primitive::Relation(r)) => {
+                assert_eq!(r.id, 20);
+                assert_eq!(
+                    r.members().collect::<Vec<_>>(),
+                    vec![
+                        ("outer", 10, RelationMemberType::Way),
+                        ("", 1, RelationMemberType::Node)
+                    ]
+                );
+            }
+            p => panic!("unexpected {:?}", p),
+        }
+    }
+
+    #[test]
+    fn truncated_o5m_is_an_error() {
+        let file = o5m();
+        // Cut inside the way dataset.
+        let cut = file.len() - 30;
+        let o5m: Box<dyn BufRead> = Box::new(Cursor::new(file[..cut].to_vec()));
+        assert!(collect(&mut Parser::new(Format::O5m, o5m)).is_err());
+
+        // A length far beyond the end of the file is not allocated.
+        let mut file = vec![0xff, 0x10];
+        varint(&mut file, 1 << 40);
+        file.extend_from_slice(&[2, 0]);
+        let o5m: Box<dyn BufRead> = Box::new(Cursor::new(file));
+        assert!(collect(&mut Parser::new(Format::O5m, o5m)).is_err());
+    }
+
+    #[test]
+    fn reader_keeps_the_error_that_ended_it() {
+        let path = temp_file("ok.osm", XML.as_bytes());
+        let mut reader = OsmReader::open(&path).unwrap();
+        // One block each for the nodes, the way and the relation.
+        assert_eq!((&mut reader).count(), 3);
+        assert!(reader.take_error().is_none());
+        fs::remove_file(&path).unwrap();
+
+        let truncated = &XML[..XML.find("<way").unwrap() + 20];
+        let path = temp_file("truncated.osm", truncated.as_bytes());
+        let mut reader = OsmReader::open(&path).unwrap();
+        (&mut reader).count();
+        assert!(reader.take_error().is_some());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// A blob of the given type holding `data` uncompressed, with its
+    /// length and header.
+    fn pbf_blob(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut blob = vec![0x0a]; // 1, bytes
+        varint(&mut blob, data.len() as u64);
+        blob.extend_from_slice(data);
+        let mut blob_header = vec![0x0a]; // 1, bytes
+        varint(&mut blob_header, kind.len() as u64);
+        blob_header.extend_from_slice(kind);
+        blob_header.push(0x18); // 3, varint
+        varint(&mut blob_header, blob.len() as u64);
+        let mut file = (blob_header.len() as u32).to_be_bytes().to_vec();
+        file.extend(blob_header);
+        file.extend(blob);
+        file
+    }
+
+    fn read_pbf(data: &[u8]) -> (usize, Option<InputError>) {
+        let path = temp_file("read.osm.pbf", data);
+        let mut reader = OsmReader::open(&path).unwrap();
+        let blobs = (&mut reader).count();
+        let error = reader.take_error();
+        fs::remove_file(&path).unwrap();
+        (blobs, error)
+    }
+
+    #[test]
+    fn truncated_pbf_is_an_error() {
+        let elements = expected();
+        let mut file = pbf_blob(b"OSMHeader", b"");
+        file.extend(pbf_blob(b"OSMData", &encode_block(&elements[..2])));
+        file.extend(pbf_blob(b"OSMData", &encode_block(&elements[2..3])));
+        let (blobs, error) = read_pbf(&file);
+        assert_eq!(blobs, 2);
+        assert!(error.is_none());
+
+        // Inside the last blob, its header and its length.
+        let last = file.len() - pbf_blob(b"OSMData", &encode_block(&elements[2..3])).len();
+        for cut in [file.len() - 1, last + 10, last + 2] {
+            let (blobs, error) = read_pbf(&file[..cut]);
+            assert_eq!(blobs, 1, "cut at {}", cut);
+            assert!(error.is_some(), "cut at {}", cut);
+        }
+
+        // A garbage length is not allocated.
+        let (blobs, error) = read_pbf(&[0xff, 0xff, 0xff, 0xff, 0]);
+        assert_eq!(blobs, 0);
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn truncated_xml_is_an_error() {
+        let parse = |xml: &str| {
+            let reader: Box<dyn BufRead> = Box::new(Cursor::new(xml.as_bytes().to_vec()));
+            collect(&mut Parser::new(Format::Xml, reader))
+        };
+        assert_eq!(parse(XML).unwrap(), expected());
+        // Every element is complete, but the root is not closed.
+        let cut = XML.find("<relation").unwrap();
+        assert!(parse(&XML[..cut]).is_err());
+        // Inside an element.
+        let cut = XML.find("<nd").unwrap();
+        assert!(parse(&XML[..cut]).is_err());
+        assert!(parse("").is_err());
+        assert!(parse("<?xml version=\"1.0\"?>\n").is_err());
+        assert!(parse("<osm version=\"0.6\"/>").unwrap().is_empty());
+    }
+
+    #[test]
+    fn extract_header_has_the_replication_state() {
+        let mut block = Vec::new();
+        block.extend_from_slice(&[0x80, 0x02]); // 32, varint
+        varint(&mut block, 1_580_472_000);
+        block.extend_from_slice(&[0x88, 0x02]); // 33, varint
+        varint(&mut block, 4711);
+        let file = pbf_blob(b"OSMHeader", &block);
+
+        let path = temp_file("header.osm.pbf", &file);
+        let header = ExtractHeader::read(&path).unwrap();
+        assert_eq!(header.replication_sequence, Some(4711));
+        assert_eq!(header.replication_timestamp, Some(1_580_472_000));
+        fs::remove_file(&path).unwrap();
+
+        let path = temp_file("header.osm", XML.as_bytes());
+        assert_eq!(
+            ExtractHeader::read(&path).unwrap(),
+            ExtractHeader::default()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+use osm_pbf_iter::RelationMemberType;
+use std::collections::HashMap;
+
+/// Version information of an element as it is stored in a PBF `Info`.
+/// Timestamps are seconds since the epoch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ElementInfo {
+    pub version: Option<u32>,
+    pub timestamp: Option<u64>,
+    pub changeset: Option<u64>,
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+}
+
+impl ElementInfo {
+    fn is_empty(&self) -> bool {
+        *self == ElementInfo::default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub id: i64,
+    pub member_type: RelationMemberType,
+    pub role: String,
+}
+
+/// An OSM object read from a format other than PBF.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Node {
+        id: i64,
+        lat: f64,
+        lon: f64,
+        info: ElementInfo,
+        tags: Vec<(String, String)>,
+    },
+    Way {
+        id: i64,
+        refs: Vec<i64>,
+        info: ElementInfo,
+        tags: Vec<(String, String)>,
+    },
+    Relation {
+        id: i64,
+        members: Vec<Member>,
+        info: ElementInfo,
+        tags: Vec<(String, String)>,
+    },
+}
+
+impl Element {
+    /// The field number of the element in a PBF `PrimitiveGroup`.
+    fn group_field(&self) -> u32 {
+        match self {
+            Element::Node { .. } => 1,
+            Element::Way { .. } => 3,
+            Element::Relation { .. } => 4,
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        match self {
+            Element::Node { id, .. } | Element::Way { id, .. } | Element::Relation { id, .. } => {
+                *id
+            }
+        }
+    }
+
+    /// Whether both elements go into the same `PrimitiveGroup`.
+    pub fn same_kind(&self, other: &Element) -> bool {
+        self.group_field() == other.group_field()
+    }
+}
+
+/// Nanodegrees per unit of the latitudes and longitudes we write, the PBF
+/// default.
+const GRANULARITY: f64 = 100.0;
+
+const VARINT: u32 = 0;
+const LENGTH_DELIMITED: u32 = 2;
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(buf, u64::from(field << 3 | wire_type));
+}
+
+fn write_uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    write_key(buf, field, VARINT);
+    write_varint(buf, value);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buf, field, LENGTH_DELIMITED);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_packed<I: IntoIterator<Item = u64>>(buf: &mut Vec<u8>, field: u32, values: I) {
+    let mut packed = Vec::new();
+    for value in values {
+        write_varint(&mut packed, value);
+    }
+    if !packed.is_empty() {
+        write_bytes(buf, field, &packed);
+    }
+}
+
+fn write_delta_packed(buf: &mut Vec<u8>, field: u32, values: &[i64]) {
+    let mut last = 0;
+    write_packed(
+        buf,
+        field,
+        values.iter().map(|&v| {
+            let delta = zigzag(v.wrapping_sub(last));
+            last = v;
+            delta
+        }),
+    );
+}
+
+/// Strings of one block, index 0 is the empty string like in every PBF.
+struct StringTable {
+    strings: Vec<String>,
+    index: HashMap<String, u32>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        StringTable {
+            strings: vec![String::new()],
+            index: HashMap::new(),
+        }
+    }
+
+    fn sid(&mut self, s: &str) -> u64 {
+        if s.is_empty() {
+            return 0;
+        }
+        if let Some(sid) = self.index.get(s) {
+            return u64::from(*sid);
+        }
+        let sid = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.index.insert(s.to_string(), sid);
+        u64::from(sid)
+    }
+}
+
+fn write_tags(buf: &mut Vec<u8>, strings: &mut StringTable, tags: &[(String, String)]) {
+    let keys: Vec<u64> = tags.iter().map(|(k, _)| strings.sid(k)).collect();
+    let vals: Vec<u64> = tags.iter().map(|(_, v)| strings.sid(v)).collect();
+    write_packed(buf, 2, keys);
+    write_packed(buf, 3, vals);
+}
+
+fn write_info(buf: &mut Vec<u8>, strings: &mut StringTable, info: &ElementInfo) {
+    if info.is_empty() {
+        return;
+    }
+    let mut message = Vec::new();
+    if let Some(version) = info.version {
+        write_uint(&mut message, 1, u64::from(version));
+    }
+    if let Some(timestamp) = info.timestamp {
+        write_uint(&mut message, 2, timestamp);
+    }
+    if let Some(changeset) = info.changeset {
+        write_uint(&mut message, 3, changeset);
+    }
+    if let Some(uid) = info.uid {
+        write_uint(&mut message, 4, u64::from(uid));
+    }
+    if let Some(user) = &info.user {
+        write_uint(&mut message, 5, strings.sid(user));
+    }
+    write_bytes(buf, 4, &message);
+}
+
+fn encode_element(element: &Element, strings: &mut StringTable) -> Vec<u8> {
+    let mut message = Vec::new();
+    match element {
+        Element::Node {
+            id,
+            lat,
+            lon,
+            info,
+            tags,
+        } => {
+            write_uint(&mut message, 1, zigzag(*id));
+            write_tags(&mut message, strings, tags);
+            write_info(&mut message, strings, info);
+            let to_units = |degrees: f64| zigzag((degrees * 1e9 / GRANULARITY).round() as i64);
+            write_uint(&mut message, 8, to_units(*lat));
+            write_uint(&mut message, 9, to_units(*lon));
+        }
+        Element::Way {
+            id,
+            refs,
+            info,
+            tags,
+        } => {
+            write_uint(&mut message, 1, *id as u64);
+            write_tags(&mut message, strings, tags);
+            write_info(&mut message, strings, info);
+            write_delta_packed(&mut message, 8, refs);
+        }
+        Element::Relation {
+            id,
+            members,
+            info,
+            tags,
+        } => {
+            write_uint(&mut message, 1, *id as u64);
+            write_tags(&mut message, strings, tags);
+            write_info(&mut message, strings, info);
+            let roles: Vec<u64> = members.iter().map(|m| strings.sid(&m.role)).collect();
+            write_packed(&mut message, 8, roles);
+            let ids: Vec<i64> = members.iter().map(|m| m.id).collect();
+            write_delta_packed(&mut message, 9, &ids);
+            write_packed(
+                &mut message,
+                10,
+                members.iter().map(|m| match m.member_type {
+                    RelationMemberType::Node => 0,
+                    RelationMemberType::Way => 1,
+                    RelationMemberType::Relation => 2,
+                }),
+            );
+        }
+    }
+    message
+}
+
+/// Encodes elements as an uncompressed PBF `PrimitiveBlock`, so they can go
+/// through `PrimitiveBlock::parse` like blocks read from a PBF file. All
+/// elements go into one `PrimitiveGroup`, and a group only holds one kind
+/// of element, so they must be of the same kind.
+pub fn encode_block(elements: &[Element]) -> Vec<u8> {
+    let mut strings = StringTable::new();
+    let mut group = Vec::new();
+    for element in elements {
+        debug_assert!(element.same_kind(&elements[0]));
+        let message = encode_element(element, &mut strings);
+        write_bytes(&mut group, element.group_field(), &message);
+    }
+
+    let mut table = Vec::new();
+    for s in strings.strings.iter() {
+        write_bytes(&mut table, 1, s.as_bytes());
+    }
+
+    let mut block = Vec::new();
+    write_bytes(&mut block, 1, &table);
+    write_bytes(&mut block, 2, &group);
+    block
+}
@@ -0,0 +1,329 @@
+use super::encode::{Element, ElementInfo, Member};
+use super::{Action, InputError};
+use osm_pbf_iter::RelationMemberType;
+use std::io::{BufRead, ErrorKind, Read};
+
+/// Number of strings the o5m string table holds.
+const STRING_TABLE_SIZE: usize = 15_000;
+/// Strings (and string pairs) that are longer are never stored in the table.
+const MAX_STORED_STRING: usize = 250;
+
+const NODE: u8 = 0x10;
+const WAY: u8 = 0x11;
+const RELATION: u8 = 0x12;
+const HEADER: u8 = 0xe0;
+const END_OF_FILE: u8 = 0xfe;
+const RESET: u8 = 0xff;
+
+/// Reads the elements of an `.o5m` or `.o5c` file, see
+/// https://wiki.openstreetmap.org/wiki/O5m for the format. Objects without a
+/// body are deletions, everything else in an `.o5c` file is a
+/// `Action::Modify` and in an `.o5m` file an `Action::Create`.
+pub struct O5mParser<R: BufRead> {
+    reader: R,
+    is_change: bool,
+    /// Stored strings, the most recent one at `strings[next - 1]`.
+    strings: Vec<Vec<u8>>,
+    next: usize,
+    id: i64,
+    timestamp: i64,
+    changeset: i64,
+    lat: i64,
+    lon: i64,
+    node_ref: i64,
+    member_ids: [i64; 3],
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, InputError> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = data
+            .split_first()
+            .ok_or_else(|| InputError::Parse("truncated o5m varint".to_string()))?;
+        *data = rest;
+        if shift < 64 {
+            value |= u64::from(byte & 0x7f) << shift;
+        }
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn read_signed(data: &mut &[u8]) -> Result<i64, InputError> {
+    let value = read_varint(data)?;
+    Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+}
+
+fn read_until_zero<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], InputError> {
+    let end = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| InputError::Parse("unterminated o5m string".to_string()))?;
+    let s = &data[..end];
+    *data = &data[end + 1..];
+    Ok(s)
+}
+
+fn to_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+impl<R: BufRead> O5mParser<R> {
+    pub fn new(reader: R) -> Self {
+        O5mParser {
+            reader,
+            is_change: false,
+            strings: vec![Vec::new(); STRING_TABLE_SIZE],
+            next: 0,
+            id: 0,
+            timestamp: 0,
+            changeset: 0,
+            lat: 0,
+            lon: 0,
+            node_ref: 0,
+            member_ids: [0; 3],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.next = 0;
+        self.id = 0;
+        self.timestamp = 0;
+        self.changeset = 0;
+        self.lat = 0;
+        self.lon = 0;
+        self.node_ref = 0;
+        self.member_ids = [0; 3];
+    }
+
+    /// Reads one string (`pair == false`) or a pair of strings, either
+    /// inline or as a reference to an earlier one. Both strings of a pair
+    /// are returned with the zero that ends the first one.
+    fn read_strings(&mut self, data: &mut &[u8], pair: bool) -> Result<Vec<u8>, InputError> {
+        if data.first() == Some(&0) {
+            *data = &data[1..];
+            let start = *data;
+            let first = read_until_zero(data)?;
+            let mut length = first.len();
+            if pair {
+                length += read_until_zero(data)?.len();
+            }
+            let strings = start[..start.len() - data.len()].to_vec();
+            if length <= MAX_STORED_STRING {
+                self.strings[self.next % STRING_TABLE_SIZE] = strings.clone();
+                self.next += 1;
+            }
+            Ok(strings)
+        } else {
+            let index = read_varint(data)? as usize;
+            if index == 0 || index > STRING_TABLE_SIZE || index > self.next {
+                return Err(InputError::Parse(format!(
+                    "invalid o5m string reference {}",
+                    index
+                )));
+            }
+            Ok(self.strings[(self.next - index) % STRING_TABLE_SIZE].clone())
+        }
+    }
+
+    fn read_pair(&mut self, data: &mut &[u8]) -> Result<(String, String), InputError> {
+        let strings = self.read_strings(data, true)?;
+        let mut parts = strings.split(|&b| b == 0);
+        let first = parts.next().unwrap_or(&[]);
+        let second = parts.next().unwrap_or(&[]);
+        Ok((to_string(first), to_string(second)))
+    }
+
+    fn read_info(&mut self, data: &mut &[u8]) -> Result<ElementInfo, InputError> {
+        let mut info = ElementInfo::default();
+        let version = read_varint(data)?;
+        if version == 0 {
+            return Ok(info);
+        }
+        info.version = Some(version as u32);
+        self.timestamp += read_signed(data)?;
+        if self.timestamp == 0 {
+            return Ok(info);
+        }
+        info.timestamp = Some(self.timestamp as u64);
+        self.changeset += read_signed(data)?;
+        info.changeset = Some(self.changeset as u64);
+
+        let strings = self.read_strings(data, true)?;
+        let mut parts = strings.split(|&b| b == 0);
+        let mut uid = parts.next().unwrap_or(&[]);
+        let user = parts.next().unwrap_or(&[]);
+        if !uid.is_empty() {
+            info.uid = Some(read_varint(&mut uid)? as u32);
+        }
+        if !user.is_empty() {
+            info.user = Some(to_string(user));
+        }
+        Ok(info)
+    }
+
+    fn read_tags(&mut self, data: &mut &[u8]) -> Result<Vec<(String, String)>, InputError> {
+        let mut tags = Vec::new();
+        while !data.is_empty() {
+            tags.push(self.read_pair(data)?);
+        }
+        Ok(tags)
+    }
+
+    fn parse_dataset(
+        &mut self,
+        kind: u8,
+        mut data: &[u8],
+    ) -> Result<(Action, Element), InputError> {
+        let data = &mut data;
+        self.id += read_signed(data)?;
+        let id = self.id;
+        let info = self.read_info(data)?;
+        let action = if data.is_empty() {
+            Action::Delete
+        } else if self.is_change {
+            Action::Modify
+        } else {
+            Action::Create
+        };
+
+        let element = match kind {
+            NODE => {
+                let (mut lat, mut lon) = (0.0, 0.0);
+                if !data.is_empty() {
+                    self.lon += read_signed(data)?;
+                    self.lat += read_signed(data)?;
+                    lon = self.lon as f64 / 1e7;
+                    lat = self.lat as f64 / 1e7;
+                }
+                Element::Node {
+                    id,
+                    lat,
+                    lon,
+                    info,
+                    tags: self.read_tags(data)?,
+                }
+            }
+            WAY => {
+                let mut refs = Vec::new();
+                if !data.is_empty() {
+                    let length = read_varint(data)? as usize;
+                    let mut section = data
+                        .get(..length)
+                        .ok_or_else(|| InputError::Parse("truncated o5m way".to_string()))?;
+                    *data = &data[length..];
+                    while !section.is_empty() {
+                        self.node_ref += read_signed(&mut section)?;
+                        refs.push(self.node_ref);
+                    }
+                }
+                Element::Way {
+                    id,
+                    refs,
+                    info,
+                    tags: self.read_tags(data)?,
+                }
+            }
+            _ => {
+                let mut members = Vec::new();
+                if !data.is_empty() {
+                    let length = read_varint(data)? as usize;
+                    let mut section = data
+                        .get(..length)
+                        .ok_or_else(|| InputError::Parse("truncated o5m relation".to_string()))?;
+                    *data = &data[length..];
+                    while !section.is_empty() {
+                        let delta = read_signed(&mut section)?;
+                        let strings = self.read_strings(&mut section, false)?;
+                        let role = &strings[..strings.len().saturating_sub(1)];
+                        let (member_type, i) = match role.first() {
+                            Some(b'0') => (RelationMemberType::Node, 0),
+                            Some(b'1') => (RelationMemberType::Way, 1),
+                            Some(b'2') => (RelationMemberType::Relation, 2),
+                            _ => {
+                                return Err(InputError::Parse(format!(
+                                    "invalid o5m member type in relation {}",
+                                    id
+                                )))
+                            }
+                        };
+                        self.member_ids[i] += delta;
+                        members.push(Member {
+                            id: self.member_ids[i],
+                            member_type,
+                            role: to_string(&role[1..]),
+                        });
+                    }
+                }
+                Element::Relation {
+                    id,
+                    members,
+                    info,
+                    tags: self.read_tags(data)?,
+                }
+            }
+        };
+        Ok((action, element))
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, InputError> {
+        let mut byte = [0u8];
+        match self.reader.read_exact(&mut byte) {
+            Ok(()) => Ok(Some(byte[0])),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads the varint length of a dataset straight from the file.
+    fn read_length(&mut self) -> Result<usize, InputError> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self
+                .read_byte()?
+                .ok_or_else(|| InputError::Parse("truncated o5m file".to_string()))?;
+            bytes.push(byte);
+            if byte & 0x80 == 0 {
+                return Ok(read_varint(&mut bytes.as_slice())? as usize);
+            }
+        }
+    }
+
+    /// The next element and its action, `None` at the end of the file.
+    pub fn next_element(&mut self) -> Result<Option<(Action, Element)>, InputError> {
+        loop {
+            let kind = match self.read_byte()? {
+                None | Some(END_OF_FILE) => return Ok(None),
+                Some(RESET) => {
+                    self.reset();
+                    continue;
+                }
+                // Datasets from 0xf0 on are a single byte without a length.
+                Some(kind) if kind >= 0xf0 => continue,
+                Some(kind) => kind,
+            };
+
+            // The length comes from the file, so only what is actually
+            // there is read instead of allocating the length up front.
+            let length = self.read_length()?;
+            let mut data = Vec::new();
+            (&mut self.reader)
+                .take(length as u64)
+                .read_to_end(&mut data)?;
+            if data.len() < length {
+                return Err(InputError::Parse(format!(
+                    "truncated o5m dataset of {} bytes",
+                    length
+                )));
+            }
+            match kind {
+                NODE | WAY | RELATION => return self.parse_dataset(kind, &data).map(Some),
+                HEADER => self.is_change = data.starts_with(b"o5c"),
+                _ => (),
+            }
+        }
+    }
+}
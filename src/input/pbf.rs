@@ -0,0 +1,135 @@
+use super::InputError;
+use osm_pbf_iter::Blob;
+use protobuf_iter::MessageIter;
+use std::io::Read;
+
+/// The largest `BlobHeader` and `Blob` the PBF format allows.
+const MAX_BLOB_HEADER_SIZE: u64 = 64 * 1024;
+const MAX_BLOB_SIZE: u64 = 32 * 1024 * 1024;
+
+/// A blob as it is in the file: the type from its `BlobHeader` and the
+/// `Blob` message.
+pub struct RawBlob {
+    pub kind: Vec<u8>,
+    pub blob: Vec<u8>,
+}
+
+/// Reads the blobs of a PBF file, see
+/// https://wiki.openstreetmap.org/wiki/PBF_Format. Unlike osm_pbf_iter's
+/// `BlobReader`, a file that ends inside a blob or a read error is an error
+/// and not the end of the file.
+pub struct PbfReader<R: Read> {
+    reader: R,
+    done: bool,
+}
+
+/// Reads exactly `length` bytes, without allocating more than the reader
+/// has when the length is garbage.
+fn read_exact_vec<R: Read>(reader: &mut R, length: u64, what: &str) -> Result<Vec<u8>, InputError> {
+    let mut data = Vec::new();
+    reader.take(length).read_to_end(&mut data)?;
+    if (data.len() as u64) < length {
+        return Err(InputError::Parse(format!(
+            "the file ends inside a {}, {} of {} bytes",
+            what,
+            data.len(),
+            length
+        )));
+    }
+    Ok(data)
+}
+
+impl<R: Read> PbfReader<R> {
+    pub fn new(reader: R) -> Self {
+        PbfReader {
+            reader,
+            done: false,
+        }
+    }
+
+    /// The next blob of any type, `None` at the end of the file.
+    pub fn next_raw(&mut self) -> Result<Option<RawBlob>, InputError> {
+        if self.done {
+            return Ok(None);
+        }
+        let result = self.read_raw();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result
+    }
+
+    fn read_raw(&mut self) -> Result<Option<RawBlob>, InputError> {
+        let mut length = Vec::new();
+        (&mut self.reader).take(4).read_to_end(&mut length)?;
+        match length.len() {
+            0 => return Ok(None),
+            4 => (),
+            n => {
+                return Err(InputError::Parse(format!(
+                    "the file ends inside a blob length, {} of 4 bytes",
+                    n
+                )))
+            }
+        }
+        let length = u64::from(u32::from_be_bytes([
+            length[0], length[1], length[2], length[3],
+        ]));
+        if length > MAX_BLOB_HEADER_SIZE {
+            return Err(InputError::Parse(format!(
+                "blob header of {} bytes",
+                length
+            )));
+        }
+        let blob_header = read_exact_vec(&mut self.reader, length, "blob header")?;
+
+        let mut kind = None;
+        let mut size = None;
+        for m in MessageIter::new(&blob_header) {
+            match m.tag {
+                1 => kind = Some(m.value.get_data().to_vec()),
+                3 => size = Some(u64::from(m.value)),
+                _ => (),
+            }
+        }
+        let (kind, size) = match (kind, size) {
+            (Some(kind), Some(size)) => (kind, size),
+            _ => {
+                return Err(InputError::Parse(
+                    "blob header without type or size".to_string(),
+                ))
+            }
+        };
+        if size > MAX_BLOB_SIZE {
+            return Err(InputError::Parse(format!("blob of {} bytes", size)));
+        }
+        let blob = read_exact_vec(&mut self.reader, size, "blob")?;
+        Ok(Some(RawBlob { kind, blob }))
+    }
+
+    /// The next `OSMData` blob, skipping the header block and blob types
+    /// other programs may add.
+    pub fn next_blob(&mut self) -> Result<Option<Blob>, InputError> {
+        while let Some(raw) = self.next_raw()? {
+            if raw.kind == b"OSMData" {
+                return blob_data(&raw.blob).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The raw or zlib compressed data of a `Blob` message. Decompression is
+/// left to the workers.
+fn blob_data(blob: &[u8]) -> Result<Blob, InputError> {
+    for m in MessageIter::new(blob) {
+        match m.tag {
+            1 => return Ok(Blob::Raw(m.value.get_data().to_vec())),
+            3 => return Ok(Blob::Zlib(m.value.get_data().to_vec())),
+            _ => (),
+        }
+    }
+    Err(InputError::Parse(
+        "blob without raw or zlib data".to_string(),
+    ))
+}
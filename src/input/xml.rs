@@ -0,0 +1,266 @@
+use super::encode::{Element, ElementInfo, Member};
+use super::{Action, InputError};
+use osm_pbf_iter::RelationMemberType;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::io::BufRead;
+
+/// Reads the elements of an `.osm` file or an osmChange (`.osc`) file. In
+/// an osmChange file every element is inside a `create`, `modify` or
+/// `delete` block, elements of an `.osm` file are all `Action::Create`.
+pub struct XmlParser<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    action: Action,
+    /// The element whose end tag has not been read yet.
+    current: Option<Element>,
+    /// The names of the open elements, from the root, so a file that ends
+    /// before all of them are closed is noticed.
+    open: Vec<Vec<u8>>,
+    /// Whether the root element was read, an empty file is not a file.
+    has_root: bool,
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, InputError> {
+    value
+        .parse()
+        .map_err(|_| InputError::Parse(format!("invalid {} {:?}", name, value)))
+}
+
+/// Seconds since the epoch of an ISO 8601 timestamp in UTC, as written by
+/// the OSM API: `2020-01-31T12:00:00Z`.
+fn parse_timestamp(value: &str) -> Result<u64, InputError> {
+    let invalid = || InputError::Parse(format!("invalid timestamp {:?}", value));
+    let digits = |range: std::ops::Range<usize>| -> Result<i64, InputError> {
+        value
+            .get(range)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(invalid)
+    };
+    if value.len() != 20 || !value.ends_with('Z') {
+        return Err(invalid());
+    }
+    let (year, month, day) = (digits(0..4)?, digits(5..7)?, digits(8..10)?);
+    let (hour, minute, second) = (digits(11..13)?, digits(14..16)?, digits(17..19)?);
+
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second;
+    if seconds < 0 {
+        return Err(invalid());
+    }
+    Ok(seconds as u64)
+}
+
+fn attributes(e: &BytesStart) -> Result<Vec<(String, String)>, InputError> {
+    let mut result = Vec::new();
+    for attribute in e.attributes() {
+        let attribute = attribute.map_err(|e| InputError::Parse(e.to_string()))?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        let value = attribute
+            .unescape_value()
+            .map_err(|e| InputError::Parse(e.to_string()))?
+            .into_owned();
+        result.push((key, value));
+    }
+    Ok(result)
+}
+
+fn get<'a>(attributes: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+fn require<'a>(
+    attributes: &'a [(String, String)],
+    element: &str,
+    key: &str,
+) -> Result<&'a str, InputError> {
+    get(attributes, key).ok_or_else(|| InputError::Parse(format!("{} without {}", element, key)))
+}
+
+fn parse_info(attributes: &[(String, String)]) -> Result<ElementInfo, InputError> {
+    Ok(ElementInfo {
+        version: get(attributes, "version")
+            .map(|v| parse_number("version", v))
+            .transpose()?,
+        timestamp: get(attributes, "timestamp")
+            .map(parse_timestamp)
+            .transpose()?,
+        changeset: get(attributes, "changeset")
+            .map(|v| parse_number("changeset", v))
+            .transpose()?,
+        uid: get(attributes, "uid")
+            .map(|v| parse_number("uid", v))
+            .transpose()?,
+        user: get(attributes, "user").map(String::from),
+    })
+}
+
+fn start_element(name: &[u8], attributes: &[(String, String)]) -> Result<Element, InputError> {
+    let element = String::from_utf8_lossy(name);
+    let id = parse_number("id", require(attributes, &element, "id")?)?;
+    let info = parse_info(attributes)?;
+    Ok(match name {
+        b"node" => Element::Node {
+            id,
+            // Deleted nodes in osmChange files have no location.
+            lat: get(attributes, "lat")
+                .map(|v| parse_number("lat", v))
+                .transpose()?
+                .unwrap_or(0.0),
+            lon: get(attributes, "lon")
+                .map(|v| parse_number("lon", v))
+                .transpose()?
+                .unwrap_or(0.0),
+            info,
+            tags: Vec::new(),
+        },
+        b"way" => Element::Way {
+            id,
+            refs: Vec::new(),
+            info,
+            tags: Vec::new(),
+        },
+        _ => Element::Relation {
+            id,
+            members: Vec::new(),
+            info,
+            tags: Vec::new(),
+        },
+    })
+}
+
+impl<R: BufRead> XmlParser<R> {
+    pub fn new(reader: R) -> Self {
+        XmlParser {
+            reader: Reader::from_reader(reader),
+            buf: Vec::new(),
+            action: Action::Create,
+            current: None,
+            open: Vec::new(),
+            has_root: false,
+        }
+    }
+
+    /// Adds a `tag`, `nd` or `member` child to the current element.
+    fn add_child(
+        &mut self,
+        name: &[u8],
+        attributes: &[(String, String)],
+    ) -> Result<(), InputError> {
+        let element = match self.current.as_mut() {
+            Some(element) => element,
+            None => return Ok(()),
+        };
+        match (name, element) {
+            (b"tag", Element::Node { tags, .. })
+            | (b"tag", Element::Way { tags, .. })
+            | (b"tag", Element::Relation { tags, .. }) => tags.push((
+                require(attributes, "tag", "k")?.to_string(),
+                require(attributes, "tag", "v")?.to_string(),
+            )),
+            (b"nd", Element::Way { refs, .. }) => {
+                refs.push(parse_number("ref", require(attributes, "nd", "ref")?)?)
+            }
+            (b"member", Element::Relation { members, .. }) => members.push(Member {
+                id: parse_number("ref", require(attributes, "member", "ref")?)?,
+                member_type: match require(attributes, "member", "type")? {
+                    "node" => RelationMemberType::Node,
+                    "way" => RelationMemberType::Way,
+                    "relation" => RelationMemberType::Relation,
+                    t => return Err(InputError::Parse(format!("unknown member type {}", t))),
+                },
+                role: get(attributes, "role").unwrap_or("").to_string(),
+            }),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// The next element and the action of the block it is in, `None` at the
+    /// end of the file.
+    pub fn next_element(&mut self) -> Result<Option<(Action, Element)>, InputError> {
+        loop {
+            self.buf.clear();
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Ok(event) => event.into_owned(),
+                Err(e) => {
+                    let position = self.reader.buffer_position();
+                    return Err(InputError::Parse(format!("{} at byte {}", e, position)));
+                }
+            };
+            let (e, empty) = match event {
+                Event::Start(e) => {
+                    self.has_root = true;
+                    self.open.push(e.name().as_ref().to_vec());
+                    (e, false)
+                }
+                Event::Empty(e) => {
+                    self.has_root = true;
+                    (e, true)
+                }
+                Event::End(e) => {
+                    self.open.pop();
+                    match e.name().as_ref() {
+                        b"node" | b"way" | b"relation" => {
+                            if let Some(element) = self.current.take() {
+                                return Ok(Some((self.action, element)));
+                            }
+                        }
+                        _ => (),
+                    }
+                    continue;
+                }
+                Event::Eof => {
+                    if let Some(name) = self.open.last() {
+                        return Err(InputError::Parse(format!(
+                            "the file ends inside <{}> at byte {}",
+                            String::from_utf8_lossy(name),
+                            self.reader.buffer_position()
+                        )));
+                    }
+                    if !self.has_root {
+                        return Err(InputError::Parse("no root element".to_string()));
+                    }
+                    return Ok(None);
+                }
+                _ => continue,
+            };
+
+            let name = e.name().as_ref().to_vec();
+            if let Some(action) = action(&name) {
+                self.action = action;
+                continue;
+            }
+            let attributes = attributes(&e)?;
+            match name.as_slice() {
+                b"node" | b"way" | b"relation" => {
+                    let element = start_element(&name, &attributes)?;
+                    if empty {
+                        return Ok(Some((self.action, element)));
+                    }
+                    self.current = Some(element);
+                }
+                _ => self.add_child(&name, &attributes)?,
+            }
+        }
+    }
+}
+
+fn action(name: &[u8]) -> Option<Action> {
+    match name {
+        b"create" => Some(Action::Create),
+        b"modify" => Some(Action::Modify),
+        b"delete" => Some(Action::Delete),
+        _ => None,
+    }
+}
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
@@ -20,6 +21,348 @@ impl Error for InvalidLocationError {
 
 pub const COORDINATE_PRECISION: u64 = 10_000_000;
 
-pub fn str_to_coord(data: String) {
-    
-}
\ No newline at end of file
+/// A location in fixed-point degrees with `COORDINATE_PRECISION`, the way
+/// PBF files and osm2pgsql store them. It takes 8 bytes instead of the 16 of
+/// two `f64`s. `new` checks the range, so only build one from the fields
+/// directly for values that were validated before.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FixedCoordinate {
+    pub lat: i32,
+    pub lon: i32,
+}
+
+impl FixedCoordinate {
+    pub fn new(lat: f64, lon: f64) -> Result<Self, InvalidLocationError> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(InvalidLocationError(format!(
+                "location {}, {} is out of range",
+                lat, lon
+            )));
+        }
+        Ok(FixedCoordinate {
+            lat: (lat * COORDINATE_PRECISION as f64).round() as i32,
+            lon: (lon * COORDINATE_PRECISION as f64).round() as i32,
+        })
+    }
+
+    pub fn lat_f64(&self) -> f64 {
+        self.lat as f64 / COORDINATE_PRECISION as f64
+    }
+
+    pub fn lon_f64(&self) -> f64 {
+        self.lon as f64 / COORDINATE_PRECISION as f64
+    }
+}
+
+impl TryFrom<(f64, f64)> for FixedCoordinate {
+    type Error = InvalidLocationError;
+
+    /// From `(lat, lon)`.
+    fn try_from((lat, lon): (f64, f64)) -> Result<Self, Self::Error> {
+        FixedCoordinate::new(lat, lon)
+    }
+}
+
+impl From<FixedCoordinate> for (f64, f64) {
+    /// To `(lat, lon)`.
+    fn from(coord: FixedCoordinate) -> Self {
+        (coord.lat_f64(), coord.lon_f64())
+    }
+}
+
+/// A piece of a coordinate string. Whitespace only separates tokens.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A number, and whether it was written with a sign.
+    Number(f64, bool),
+    Degrees,
+    Minutes,
+    Seconds,
+    /// `N`, `S`, `E` or `W`.
+    Hemisphere(char),
+    /// `lat`, `lon` and their spellings, with `true` for the latitude.
+    Label(bool),
+    /// `:` or `=` after a label.
+    Assign,
+    /// `,`, `;` or `/` between latitude and longitude.
+    Separator,
+}
+
+fn tokenize(data: &str) -> Result<Vec<Token>, InvalidLocationError> {
+    let chars: Vec<char> = data.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let starts_number = |j: usize| {
+            chars
+                .get(j)
+                .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+        };
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || c == '.'
+            || (matches!(c, '+' | '-' | '−') && starts_number(i + 1))
+        {
+            let signed = matches!(c, '+' | '-' | '−');
+            let start = if signed { i + 1 } else { i };
+            let mut end = start;
+            while starts_number(end) {
+                end += 1;
+            }
+            let text: String = chars[start..end].iter().collect();
+            let value: f64 = text
+                .parse()
+                .map_err(|_| InvalidLocationError(format!("invalid number '{}'", text)))?;
+            let value = if c == '-' || c == '−' {
+                -value
+            } else {
+                value
+            };
+            tokens.push(Token::Number(value, signed));
+            i = end;
+        } else if c.is_alphabetic() {
+            let start = i;
+            while chars.get(i).is_some_and(|c| c.is_alphabetic()) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect::<String>().to_lowercase();
+            tokens.push(match word.as_str() {
+                "n" | "s" | "e" | "w" => {
+                    Token::Hemisphere(word.to_ascii_uppercase().chars().next().unwrap())
+                }
+                "lat" | "latitude" => Token::Label(true),
+                "lon" | "lng" | "long" | "longitude" => Token::Label(false),
+                _ => return Err(InvalidLocationError(format!("unexpected '{}'", word))),
+            });
+        } else {
+            tokens.push(match c {
+                '°' | 'º' => Token::Degrees,
+                // Two apostrophes are often typed for seconds.
+                '\'' | '′' | '’' if matches!(chars.get(i + 1), Some('\'' | '′' | '’')) => {
+                    i += 1;
+                    Token::Seconds
+                }
+                '\'' | '′' | '’' => Token::Minutes,
+                '"' | '″' | '”' => Token::Seconds,
+                ':' | '=' => Token::Assign,
+                ',' | ';' | '/' => Token::Separator,
+                _ => return Err(InvalidLocationError(format!("unexpected '{}'", c))),
+            });
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+/// One half of a coordinate string, in degrees, and whether it is the
+/// latitude if a label or hemisphere says so.
+struct Component {
+    degrees: f64,
+    is_lat: Option<bool>,
+}
+
+/// The minutes or seconds at `tokens[i]`: a number without a sign that is
+/// followed by its `mark` or a hemisphere letter.
+fn marked_part(tokens: &[Token], i: usize, mark: Token) -> Option<f64> {
+    match (tokens.get(i), tokens.get(i + 1)) {
+        (Some(Token::Number(value, false)), Some(next))
+            if *next == mark || matches!(next, Token::Hemisphere(_)) =>
+        {
+            Some(*value)
+        }
+        _ => None,
+    }
+}
+
+/// Parses `[label] [hemisphere] degrees [° [minutes [' [seconds ["]]]]]
+/// [hemisphere]` starting at `tokens[*i]`. Minutes only follow degrees
+/// marked with `°`, and seconds only minutes marked with `'`, so two plain
+/// numbers are always read as latitude and longitude. The last mark may be
+/// left out before a hemisphere letter, as in `48°51.5N`.
+fn parse_component(tokens: &[Token], i: &mut usize) -> Result<Component, InvalidLocationError> {
+    let invalid = |msg: &str| Err(InvalidLocationError(msg.to_string()));
+    let mut is_lat = None;
+    if let Some(Token::Label(lat)) = tokens.get(*i) {
+        is_lat = Some(*lat);
+        *i += 1;
+        if tokens.get(*i) == Some(&Token::Assign) {
+            *i += 1;
+        }
+    }
+
+    let mut hemisphere = None;
+    if let Some(Token::Hemisphere(h)) = tokens.get(*i) {
+        hemisphere = Some(*h);
+        *i += 1;
+    }
+
+    let (mut degrees, signed) = match tokens.get(*i) {
+        Some(Token::Number(value, signed)) => (*value, *signed),
+        _ => return invalid("expected a number of degrees"),
+    };
+    *i += 1;
+
+    if tokens.get(*i) == Some(&Token::Degrees) {
+        *i += 1;
+        if let Some(minutes) = marked_part(tokens, *i, Token::Minutes) {
+            if degrees.fract() != 0.0 || minutes >= 60.0 {
+                return invalid("invalid minutes");
+            }
+            *i += 1;
+            let mut offset = minutes / 60.0;
+            if tokens.get(*i) == Some(&Token::Minutes) {
+                *i += 1;
+                if let Some(seconds) = marked_part(tokens, *i, Token::Seconds) {
+                    if minutes.fract() != 0.0 || seconds >= 60.0 {
+                        return invalid("invalid seconds");
+                    }
+                    *i += 1;
+                    offset += seconds / 3600.0;
+                    if tokens.get(*i) == Some(&Token::Seconds) {
+                        *i += 1;
+                    }
+                }
+            }
+            degrees += if degrees.is_sign_negative() {
+                -offset
+            } else {
+                offset
+            };
+        }
+    }
+
+    // With a leading hemisphere, a letter after the number belongs to the
+    // next component, as in `N 48.85 E 2.35`.
+    if hemisphere.is_none() {
+        if let Some(Token::Hemisphere(h)) = tokens.get(*i) {
+            hemisphere = Some(*h);
+            *i += 1;
+        }
+    }
+
+    if let Some(h) = hemisphere {
+        if signed {
+            return invalid("both a sign and a hemisphere");
+        }
+        let lat = h == 'N' || h == 'S';
+        if is_lat.is_some_and(|is_lat| is_lat != lat) {
+            return invalid("the hemisphere does not match the label");
+        }
+        is_lat = Some(lat);
+        if h == 'S' || h == 'W' {
+            degrees = -degrees;
+        }
+    }
+
+    Ok(Component { degrees, is_lat })
+}
+
+/// Parses a coordinate typed into a search box, the way Nominatim treats
+/// such queries as a lookup of that location. Accepted are decimal degrees
+/// (`48.85, 2.35`, `-33.9 151.2`), hemisphere letters before or after the
+/// number (`48.85N 2.35E`, `S 33.9 E 151.2`), degrees, minutes and seconds
+/// (`48°51'29"N 2°21'03"E`) and degrees with decimal minutes
+/// (`48°51.48'N, 2°21.05'E`). The latitude comes first unless the
+/// hemispheres or `lat`/`lon` labels (`lon=2.35 lat=48.85`) say otherwise.
+pub fn str_to_coord(data: &str) -> Result<FixedCoordinate, InvalidLocationError> {
+    let invalid = || InvalidLocationError(format!("'{}' is not a coordinate", data));
+    let tokens = tokenize(data).map_err(|_| invalid())?;
+    let mut i = 0;
+    let first = parse_component(&tokens, &mut i).map_err(|_| invalid())?;
+    if tokens.get(i) == Some(&Token::Separator) {
+        i += 1;
+    }
+    let second = parse_component(&tokens, &mut i).map_err(|_| invalid())?;
+    if i != tokens.len() {
+        return Err(invalid());
+    }
+
+    let first_is_lat = match (first.is_lat, second.is_lat) {
+        (Some(a), Some(b)) if a == b => return Err(invalid()),
+        (Some(a), _) => a,
+        (None, Some(b)) => !b,
+        (None, None) => true,
+    };
+    if first_is_lat {
+        FixedCoordinate::new(first.degrees, second.degrees)
+    } else {
+        FixedCoordinate::new(second.degrees, first.degrees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coord(data: &str) -> (f64, f64) {
+        str_to_coord(data)
+            .unwrap_or_else(|e| panic!("{}: {}", data, e))
+            .into()
+    }
+
+    #[test]
+    fn decimal_degrees() {
+        assert_eq!(coord("48.85, 2.35"), (48.85, 2.35));
+        assert_eq!(coord("48.85 2.35"), (48.85, 2.35));
+        assert_eq!(coord("+48.85;+2.35"), (48.85, 2.35));
+        assert_eq!(coord("lon=2.35 lat=48.85"), (48.85, 2.35));
+    }
+
+    #[test]
+    fn negative_values() {
+        assert_eq!(coord("-33.9, 151.2"), (-33.9, 151.2));
+        assert_eq!(coord("-33.9 -70.65"), (-33.9, -70.65));
+        assert_eq!(coord("−33.9 −70.65"), (-33.9, -70.65));
+        assert_eq!(coord("33.9S 70.65W"), (-33.9, -70.65));
+        assert_eq!(coord("W 70.65 S 33.9"), (-33.9, -70.65));
+        // The sign also applies to the minutes of degrees below one.
+        assert_eq!(coord("-0°30' -0°15'"), (-0.5, -0.25));
+        assert_eq!(coord("-33°54' 151°12'"), (-33.9, 151.2));
+    }
+
+    #[test]
+    fn missing_fraction() {
+        assert_eq!(coord("48, 2"), (48.0, 2.0));
+        assert_eq!(coord("48. 2."), (48.0, 2.0));
+        assert_eq!(coord(".5 .25"), (0.5, 0.25));
+        assert_eq!(coord("-48 -2"), (-48.0, -2.0));
+        assert_eq!(coord("48N 2E"), (48.0, 2.0));
+    }
+
+    #[test]
+    fn degrees_minutes_seconds() {
+        let (lat, lon) = coord("48°51'29\"N 2°21'03\"E");
+        assert!((lat - 48.858_055_6).abs() < 1e-7);
+        assert!((lon - 2.350_833_3).abs() < 1e-7);
+        assert_eq!(coord("48°51.5N, 2°21E"), (48.858_333_3, 2.35));
+        assert_eq!(coord("48°51'29'' 2°"), coord("48°51'29\" 2"));
+    }
+
+    #[test]
+    fn malformed_input() {
+        for data in [
+            "",
+            "48.85",
+            "48.85,",
+            ", 2.35",
+            "48.85, 2.35, 1",
+            "1.2.3, 4",
+            "abc",
+            "48.85 x 2.35",
+            "48.85 - 2.35",
+            "48.85N 2.35N",
+            "lat=48.85 lat=2.35",
+            "lon=48.85N 2.35",
+            "-48.85N 2.35",
+            "48°61' 2",
+            "48°30'61\" 2",
+            "48.5°30' 2",
+            "91, 0",
+            "0, 181",
+        ] {
+            assert!(str_to_coord(data).is_err(), "{:?} was accepted", data);
+        }
+    }
+}
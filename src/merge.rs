@@ -0,0 +1,121 @@
+use crate::place::OsmId;
+use dashmap::DashMap;
+use osm_pbf_iter::info::Info;
+
+/// The version and timestamp of an object, compared in that order. Objects
+/// without metadata lose against any object that has it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ObjectVersion {
+    pub version: Option<u32>,
+    pub timestamp: Option<u64>,
+}
+
+impl ObjectVersion {
+    pub fn from_info(info: &Option<Info>) -> Self {
+        match info {
+            Some(info) => ObjectVersion {
+                version: info.version,
+                timestamp: info.timestamp,
+            },
+            None => ObjectVersion::default(),
+        }
+    }
+}
+
+/// The newest version seen of every object, used when several extracts are
+/// merged into one store.
+pub type VersionDB = DashMap<OsmId, ObjectVersion>;
+
+/// Whether the same or a newer version of the object was already imported,
+/// in which case the caller skips it. Otherwise its version is recorded.
+/// Always `false` when no `VersionDB` is given, i.e. when not merging.
+pub fn is_duplicate(versions: Option<&VersionDB>, id: OsmId, info: &Option<Info>) -> bool {
+    match versions {
+        Some(versions) => !claim(versions, id, ObjectVersion::from_info(info)),
+        None => false,
+    }
+}
+
+/// Records `version` for `id` if it is newer than the stored one.
+fn claim(versions: &VersionDB, id: OsmId, version: ObjectVersion) -> bool {
+    let mut newer = true;
+    versions
+        .entry(id)
+        .and_modify(|stored| {
+            if version > *stored {
+                *stored = version;
+            } else {
+                newer = false;
+            }
+        })
+        .or_insert(version);
+    newer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(version: u32, timestamp: u64) -> Option<Info<'static>> {
+        Some(Info {
+            version: Some(version),
+            timestamp: Some(timestamp),
+            changeset: None,
+            uid: None,
+            user: None,
+            visible: None,
+        })
+    }
+
+    #[test]
+    fn the_same_version_is_skipped() {
+        let versions = VersionDB::default();
+        assert!(!is_duplicate(
+            Some(&versions),
+            OsmId::Node(1),
+            &info(3, 100)
+        ));
+        assert!(is_duplicate(Some(&versions), OsmId::Node(1), &info(3, 100)));
+        // Another object with the same id is not the same object.
+        assert!(!is_duplicate(Some(&versions), OsmId::Way(1), &info(3, 100)));
+    }
+
+    #[test]
+    fn a_newer_version_wins() {
+        let versions = VersionDB::default();
+        assert!(!is_duplicate(
+            Some(&versions),
+            OsmId::Node(1),
+            &info(3, 100)
+        ));
+        assert!(!is_duplicate(Some(&versions), OsmId::Node(1), &info(4, 50)));
+        assert!(is_duplicate(Some(&versions), OsmId::Node(1), &info(3, 200)));
+        assert!(!is_duplicate(Some(&versions), OsmId::Node(1), &info(4, 60)));
+        assert_eq!(
+            *versions.get(&OsmId::Node(1)).unwrap(),
+            ObjectVersion {
+                version: Some(4),
+                timestamp: Some(60)
+            }
+        );
+    }
+
+    #[test]
+    fn objects_without_info_lose() {
+        let versions = VersionDB::default();
+        assert!(!is_duplicate(Some(&versions), OsmId::Relation(1), &None));
+        assert!(is_duplicate(Some(&versions), OsmId::Relation(1), &None));
+        assert!(!is_duplicate(
+            Some(&versions),
+            OsmId::Relation(1),
+            &info(1, 1)
+        ));
+        assert!(is_duplicate(Some(&versions), OsmId::Relation(1), &None));
+    }
+
+    #[test]
+    fn nothing_is_a_duplicate_when_not_merging() {
+        assert!(!is_duplicate(None, OsmId::Node(1), &info(3, 100)));
+        assert!(!is_duplicate(None, OsmId::Node(1), &info(3, 100)));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::node::Coordinate;
+use crate::relation::RelationMember;
+use crate::way::{Area, ClosedLineString, Polygon, WayDB, WayProcessingError};
+use osm_pbf_iter::RelationMemberType;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RingAssemblyError {
+    /// A member way of the relation is not in the `WAY_DB`.
+    MissingWay(i64),
+    /// The segments could not be joined into a closed ring. Holds the node
+    /// ids of the two loose ends.
+    OpenRing(i64, i64),
+    /// An inner ring that does not lie inside any of the outer rings.
+    OrphanInnerRing(i64),
+    NoOuterRing,
+    InvalidRing(WayProcessingError),
+}
+
+impl RingAssemblyError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RingAssemblyError::MissingWay(_) => "MissingWay",
+            RingAssemblyError::OpenRing(..) => "OpenRing",
+            RingAssemblyError::OrphanInnerRing(_) => "OrphanInnerRing",
+            RingAssemblyError::NoOuterRing => "NoOuterRing",
+            RingAssemblyError::InvalidRing(_) => "InvalidRing",
+        }
+    }
+}
+
+pub fn is_area_relation(relation_type: Option<&String>) -> bool {
+    matches!(
+        relation_type.map(|t| t.as_str()),
+        Some("multipolygon") | Some("boundary")
+    )
+}
+
+/// Builds the polygons of a `type=multipolygon` or `type=boundary` relation
+/// from the geometries of its member ways. Members with an empty role are
+/// treated as outer, like osm2pgsql does.
+pub fn assemble_area(
+    members: &[RelationMember],
+    way_db: &WayDB,
+) -> Result<Area, RingAssemblyError> {
+    let mut outer_segments: Vec<Vec<Coordinate>> = Vec::new();
+    let mut inner_segments: Vec<Vec<Coordinate>> = Vec::new();
+
+    for member in members
+        .iter()
+        .filter(|m| m.member_type == RelationMemberType::Way)
+    {
+        let coords = match way_db.get(&member.id) {
+            Some(way) => way.value().coords_shape().coords().to_vec(),
+            None => return Err(RingAssemblyError::MissingWay(member.id)),
+        };
+
+        match member.role.as_str() {
+            "inner" => inner_segments.push(coords),
+            "outer" | "" => outer_segments.push(coords),
+            // Boundaries also list `subarea`, `admin_centre` and `label`
+            // members, which are not part of the geometry.
+            _ => (),
+        }
+    }
+
+    assemble_polygons(outer_segments, inner_segments)
+}
+
+/// Joins the outer and inner segments into rings and puts every inner ring
+/// into the polygon of the outer ring it lies in.
+fn assemble_polygons(
+    outer_segments: Vec<Vec<Coordinate>>,
+    inner_segments: Vec<Vec<Coordinate>>,
+) -> Result<Area, RingAssemblyError> {
+    let outers = assemble_rings(outer_segments)?;
+    let inners = assemble_rings(inner_segments)?;
+
+    if outers.is_empty() {
+        return Err(RingAssemblyError::NoOuterRing);
+    }
+
+    let mut polygons: Vec<Polygon> = outers
+        .into_iter()
+        .map(|outer| Polygon {
+            outer,
+            inners: Vec::new(),
+        })
+        .collect();
+
+    for inner in inners {
+        match polygons.iter_mut().find(|p| is_inside(&inner, &p.outer)) {
+            Some(polygon) => polygon.inners.push(inner),
+            None => return Err(RingAssemblyError::OrphanInnerRing(inner.coords()[0].id)),
+        }
+    }
+
+    Ok(Area(polygons))
+}
+
+/// Whether an inner ring lies inside an outer ring. Inner rings often touch
+/// their outer ring, so only a vertex that is not on the outer ring is
+/// tested. A ring made only of vertices of the outer ring is inside it.
+fn is_inside(inner: &ClosedLineString, outer: &ClosedLineString) -> bool {
+    let on_outer: HashSet<_> = outer.coords().iter().map(|c| c.location).collect();
+    match inner
+        .coords()
+        .iter()
+        .find(|c| !on_outer.contains(&c.location))
+    {
+        Some(vertex) => outer.contains(vertex),
+        None => true,
+    }
+}
+
+/// Joins way segments end to end, matching on node ids, until every
+/// segment is part of a closed ring.
+fn assemble_rings(
+    mut segments: Vec<Vec<Coordinate>>,
+) -> Result<Vec<ClosedLineString>, RingAssemblyError> {
+    let mut rings = Vec::new();
+
+    while let Some(mut ring) = segments.pop() {
+        while ring.first().map(|c| c.id) != ring.last().map(|c| c.id) {
+            let end = ring.last().unwrap().id;
+            let next = segments.iter().position(|s| {
+                s.first().map(|c| c.id) == Some(end) || s.last().map(|c| c.id) == Some(end)
+            });
+
+            match next {
+                Some(i) => {
+                    let mut segment = segments.swap_remove(i);
+                    if segment.first().unwrap().id != end {
+                        segment.reverse();
+                    }
+                    ring.extend(segment.into_iter().skip(1));
+                }
+                None => {
+                    return Err(RingAssemblyError::OpenRing(ring.first().unwrap().id, end));
+                }
+            }
+        }
+
+        rings.push(ClosedLineString::new(ring).map_err(RingAssemblyError::InvalidRing)?);
+    }
+
+    Ok(rings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::FixedCoordinate;
+
+    /// A segment from `(id, lat, lon)` triples.
+    fn segment(nodes: &[(i64, f64, f64)]) -> Vec<Coordinate> {
+        nodes
+            .iter()
+            .map(|(id, lat, lon)| Coordinate::new(*id, FixedCoordinate::new(*lat, *lon).unwrap()))
+            .collect()
+    }
+
+    fn square(first_id: i64, lat: f64, lon: f64, size: f64) -> Vec<Coordinate> {
+        segment(&[
+            (first_id, lat, lon),
+            (first_id + 1, lat, lon + size),
+            (first_id + 2, lat + size, lon + size),
+            (first_id + 3, lat + size, lon),
+            (first_id, lat, lon),
+        ])
+    }
+
+    #[test]
+    fn joins_segments_into_a_ring() {
+        let outer = vec![
+            segment(&[(1, 0.0, 0.0), (2, 0.0, 1.0), (3, 1.0, 1.0)]),
+            // Reversed, so it has to be flipped to continue the ring.
+            segment(&[(1, 0.0, 0.0), (4, 1.0, 0.0), (3, 1.0, 1.0)]),
+        ];
+        let area = assemble_polygons(outer, Vec::new()).unwrap();
+        assert_eq!(area.0.len(), 1);
+        let ids: Vec<i64> = area.0[0].outer.coords().iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn unclosed_ring_is_an_error() {
+        let outer = vec![segment(&[(1, 0.0, 0.0), (2, 0.0, 1.0), (3, 1.0, 1.0)])];
+        assert_eq!(
+            assemble_polygons(outer, Vec::new()),
+            Err(RingAssemblyError::OpenRing(1, 3))
+        );
+    }
+
+    #[test]
+    fn no_outer_ring_is_an_error() {
+        assert_eq!(
+            assemble_polygons(Vec::new(), vec![square(1, 0.0, 0.0, 1.0)]),
+            Err(RingAssemblyError::NoOuterRing)
+        );
+    }
+
+    #[test]
+    fn inner_rings_go_to_their_outer_ring() {
+        let outers = vec![square(1, 0.0, 0.0, 1.0), square(11, 0.0, 5.0, 1.0)];
+        let inners = vec![square(21, 0.2, 5.2, 0.5)];
+        let area = assemble_polygons(outers, inners).unwrap();
+        assert_eq!(area.0.len(), 2);
+        let with_hole: Vec<i64> = area
+            .0
+            .iter()
+            .filter(|p| !p.inners.is_empty())
+            .map(|p| p.outer.coords()[0].id)
+            .collect();
+        assert_eq!(with_hole, vec![11]);
+    }
+
+    #[test]
+    fn inner_ring_may_touch_the_outer_ring() {
+        let outer = vec![square(1, 0.0, 0.0, 1.0)];
+        // Starts at the corner node 1 of the outer ring.
+        let inner = vec![segment(&[
+            (1, 0.0, 0.0),
+            (21, 0.2, 0.5),
+            (22, 0.5, 0.5),
+            (1, 0.0, 0.0),
+        ])];
+        let area = assemble_polygons(outer, inner).unwrap();
+        assert_eq!(area.0[0].inners.len(), 1);
+    }
+
+    #[test]
+    fn inner_ring_outside_all_outer_rings_is_an_error() {
+        let outer = vec![square(1, 0.0, 0.0, 1.0)];
+        let inner = vec![square(21, 5.0, 5.0, 0.5)];
+        assert_eq!(
+            assemble_polygons(outer, inner),
+            Err(RingAssemblyError::OrphanInnerRing(21))
+        );
+    }
+}
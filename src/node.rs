@@ -1,83 +1,54 @@
+use crate::location::FixedCoordinate;
+use crate::node_store::{NodeLocationStore, NodeStoreError};
+use crate::style::{OsmType, Style};
 use dashmap::DashMap;
 use osm_pbf_iter::*;
-use std::collections::{HashMap, HashSet};
-use std::iter::FromIterator;
+use std::collections::HashMap;
 
-pub type NodeCoordDB = DashMap<u64, __DBCoordinate>;
+pub type NodeCoordDB = DashMap<i64, FixedCoordinate>;
 pub type NodeTags = HashMap<String, String>;
 
-pub type NodeTagsDB = DashMap<u64, HashMap<String, String>>;
-
-#[derive(Debug, PartialOrd, PartialEq, Copy, Clone)]
-pub struct __DBCoordinate {
-    pub lat: f64,
-    pub lon: f64,
-}
+pub type NodeTagsDB = DashMap<i64, HashMap<String, String>>;
 
+/// A node location together with the node's id, which ring assembly
+/// matches on.
 #[derive(Debug, PartialOrd, PartialEq, Copy, Clone)]
 pub struct Coordinate {
-    pub id: u64,
-    pub lat: f64,
-    pub lon: f64,
+    pub id: i64,
+    pub location: FixedCoordinate,
 }
 
 impl Coordinate {
-    pub fn from_db_coord(id: u64, db_coord: __DBCoordinate) -> Self {
-        Coordinate {
-            id: id,
-            lat: db_coord.lat,
-            lon: db_coord.lon,
-        }
+    pub fn new(id: i64, location: FixedCoordinate) -> Self {
+        Coordinate { id, location }
     }
 }
 
 pub fn process_node<'a>(
     n: &'a Node<'a>,
-    node_coord_db: &NodeCoordDB,
-    generic_keys: &HashSet<&'static str>,
+    node_store: &dyn NodeLocationStore,
+    style: &Style,
     node_tags_db: &NodeTagsDB,
-) -> Option<u64> {
-    node_coord_db.insert(
-        n.id,
-        __DBCoordinate {
-            lat: n.lat,
-            lon: n.lon,
-        },
-    );
-    if n.tags.len() > 0 {
-        let tags: HashMap<String, String> = HashMap::from_iter(
-            n.tags
-                .iter()
-                .map(|(k, v)| (String::from(*k), String::from(*v))),
-        );
-
-        let keys: Vec<&str> = tags.iter().map(|(k, _v)| k.as_str()).collect::<Vec<&str>>();
-
-        let filtered_tag_keys: Vec<String> = HashSet::from_iter(keys)
-            .intersection(generic_keys)
-            .cloned()
-            .map(|s| String::from(s))
-            .collect();
-
-        if filtered_tag_keys.len() < 1 {
-            return None;
-        }
-
-        let mut filtered_tags: HashMap<String, String> = HashMap::new();
-        for k in filtered_tag_keys {
-            if let Some(v) = tags.get(&k) {
-                filtered_tags.insert(k, v.to_string());
-            }
+) -> Result<Option<u64>, NodeStoreError> {
+    let id = n.id as i64;
+    let location =
+        FixedCoordinate::new(n.lat, n.lon).map_err(|e| NodeStoreError::InvalidLocation(id, e))?;
+    node_store.insert(id, location)?;
+    if !n.tags.is_empty() {
+        let filtered_tags = style.filter_tags(OsmType::Node, n.tags.iter().cloned());
+
+        if filtered_tags.is_empty() {
+            return Ok(None);
         }
 
         let mut size: u64 = 0;
         for (_k, v) in filtered_tags.iter() {
-            size += v.as_bytes().len() as u64;
+            size += v.len() as u64;
         }
 
-        node_tags_db.insert(n.id, filtered_tags);
-        Some(size)
+        node_tags_db.insert(id, filtered_tags);
+        Ok(Some(size))
     } else {
-        return None;
+        Ok(None)
     }
 }
@@ -0,0 +1,294 @@
+use crate::location::{FixedCoordinate, InvalidLocationError, COORDINATE_PRECISION};
+use crate::node::NodeCoordDB;
+use dashmap::DashMap;
+use memmap2::MmapMut;
+use std::cmp::max;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Debug)]
+pub enum NodeStoreError {
+    /// The node id is negative or larger than the store was created for.
+    IdOutOfRange(i64),
+    /// The node's location is outside of the valid range.
+    InvalidLocation(i64, InvalidLocationError),
+}
+
+impl NodeStoreError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NodeStoreError::IdOutOfRange(_) => "IdOutOfRange",
+            NodeStoreError::InvalidLocation(..) => "InvalidLocation",
+        }
+    }
+}
+
+/// Where node locations live while ways are being built. The in-memory
+/// `NodeCoordDB` is fine for extracts, the `FlatNodeStore` is for planets.
+pub trait NodeLocationStore: Send + Sync {
+    fn insert(&self, id: i64, coord: FixedCoordinate) -> Result<(), NodeStoreError>;
+    fn get(&self, id: i64) -> Option<FixedCoordinate>;
+    /// Removes a deleted node and returns its last location.
+    fn remove(&self, id: i64) -> Option<FixedCoordinate>;
+    fn len(&self) -> usize;
+    /// Makes sure everything stored so far survives a crash.
+    fn flush(&self) -> io::Result<()>;
+    /// The last replication diff applied to the stored locations, if the
+    /// store outlives the import and has one.
+    fn replication_sequence(&self) -> Option<u64>;
+    /// Flushes the locations and then records `sequence` as the last diff
+    /// they include, so the store never names a diff it does not hold.
+    fn set_replication_sequence(&self, sequence: u64) -> io::Result<()>;
+}
+
+impl NodeLocationStore for NodeCoordDB {
+    fn insert(&self, id: i64, coord: FixedCoordinate) -> Result<(), NodeStoreError> {
+        DashMap::insert(self, id, coord);
+        Ok(())
+    }
+
+    fn get(&self, id: i64) -> Option<FixedCoordinate> {
+        DashMap::get(self, &id).map(|c| *c.value())
+    }
+
+    fn remove(&self, id: i64) -> Option<FixedCoordinate> {
+        DashMap::remove(self, &id).map(|(_id, c)| c)
+    }
+
+    fn len(&self) -> usize {
+        DashMap::len(self)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Nothing outlives an import in memory, so there is nothing to resume.
+    fn replication_sequence(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_replication_sequence(&self, _sequence: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Dense array of node locations in a memory-mapped file, indexed by node
+/// id. Every slot is 8 bytes: the latitude and longitude as fixed-point `i32`
+/// with `COORDINATE_PRECISION`, each biased so that an all-zero slot means
+/// the node is not stored. The file is sparse, so only the pages holding
+/// nodes that exist take up disk space. OSM node ids start at 1, so the slot
+/// of id 0 holds the replication sequence of the locations instead, plus one
+/// so that zero means none.
+pub struct FlatNodeStore {
+    mmap: MmapMut,
+    /// The start of the mapping, taken from `as_mut_ptr` when it was made,
+    /// so writing through the atomics does not go through a shared borrow.
+    slots: *mut u8,
+    capacity: u64,
+    count: AtomicUsize,
+}
+
+// The mapping is only accessed through atomics.
+unsafe impl Send for FlatNodeStore {}
+unsafe impl Sync for FlatNodeStore {}
+
+const LAT_BIAS: i64 = 90 * COORDINATE_PRECISION as i64 + 1;
+const LON_BIAS: i64 = 180 * COORDINATE_PRECISION as i64 + 1;
+
+/// The slot that holds the replication sequence.
+const SEQUENCE_SLOT: usize = 0;
+
+impl FlatNodeStore {
+    /// Creates (or truncates) the file at `path` with room for node ids up
+    /// to and including `max_id`.
+    pub fn create<P: AsRef<Path>>(path: P, max_id: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Self::map(file, max_id + 1)
+    }
+
+    /// Opens the file at `path` keeping the locations stored in it, and
+    /// grows it if it has no room for `max_id`. Counting the stored nodes
+    /// reads the whole file once.
+    pub fn open<P: AsRef<Path>>(path: P, max_id: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len();
+        if len % 8 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("node store size {} is not a multiple of 8", len),
+            ));
+        }
+        let capacity = max(len / 8, max_id + 1);
+        let mut store = Self::map(file, capacity)?;
+        let count = store
+            .slots()
+            .iter()
+            .skip(SEQUENCE_SLOT + 1)
+            .filter(|slot| slot.load(Ordering::Relaxed) != 0)
+            .count();
+        store.count = AtomicUsize::new(count);
+        Ok(store)
+    }
+
+    fn map(file: File, capacity: u64) -> io::Result<Self> {
+        if file.metadata()?.len() < capacity * 8 {
+            file.set_len(capacity * 8)?;
+        }
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+        let slots = mmap.as_mut_ptr();
+
+        Ok(FlatNodeStore {
+            mmap,
+            slots,
+            capacity,
+            count: AtomicUsize::new(0),
+        })
+    }
+
+    fn slots(&self) -> &[AtomicU64] {
+        // The mapping is page aligned and lives as long as `self`, and all
+        // access goes through atomics so workers can write concurrently.
+        unsafe {
+            std::slice::from_raw_parts(self.slots as *const AtomicU64, self.capacity as usize)
+        }
+    }
+
+    /// Whether `id` has a slot for its location.
+    fn is_node_slot(&self, id: i64) -> bool {
+        id > SEQUENCE_SLOT as i64 && (id as u64) < self.capacity
+    }
+
+    fn encode(coord: FixedCoordinate) -> u64 {
+        let lat = coord.lat as i64 + LAT_BIAS;
+        let lon = coord.lon as i64 + LON_BIAS;
+        ((lat as u64) << 32) | (lon as u64 & 0xFFFF_FFFF)
+    }
+
+    fn decode(slot: u64) -> FixedCoordinate {
+        let lat = (slot >> 32) as i64 - LAT_BIAS;
+        let lon = (slot & 0xFFFF_FFFF) as i64 - LON_BIAS;
+        FixedCoordinate {
+            lat: lat as i32,
+            lon: lon as i32,
+        }
+    }
+}
+
+impl NodeLocationStore for FlatNodeStore {
+    fn insert(&self, id: i64, coord: FixedCoordinate) -> Result<(), NodeStoreError> {
+        if !self.is_node_slot(id) {
+            return Err(NodeStoreError::IdOutOfRange(id));
+        }
+        if self.slots()[id as usize].swap(Self::encode(coord), Ordering::Relaxed) == 0 {
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: i64) -> Option<FixedCoordinate> {
+        if !self.is_node_slot(id) {
+            return None;
+        }
+        match self.slots()[id as usize].load(Ordering::Relaxed) {
+            0 => None,
+            slot => Some(Self::decode(slot)),
+        }
+    }
+
+    fn remove(&self, id: i64) -> Option<FixedCoordinate> {
+        if !self.is_node_slot(id) {
+            return None;
+        }
+        match self.slots()[id as usize].swap(0, Ordering::Relaxed) {
+            0 => None,
+            slot => {
+                self.count.fetch_sub(1, Ordering::Relaxed);
+                Some(Self::decode(slot))
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.mmap.flush()
+    }
+
+    fn replication_sequence(&self) -> Option<u64> {
+        match self.slots()[SEQUENCE_SLOT].load(Ordering::Relaxed) {
+            0 => None,
+            slot => Some(slot - 1),
+        }
+    }
+
+    fn set_replication_sequence(&self, sequence: u64) -> io::Result<()> {
+        self.mmap.flush()?;
+        self.slots()[SEQUENCE_SLOT].store(sequence + 1, Ordering::Relaxed);
+        self.mmap.flush_range(SEQUENCE_SLOT * 8, 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn flat_store_keeps_locations_when_reopened() {
+        let path = env::temp_dir().join(format!("nominatim_rs-test-{}.flat", std::process::id()));
+        let coord = FixedCoordinate::new(-33.9, 151.2).unwrap();
+        {
+            let store = FlatNodeStore::create(&path, 100).unwrap();
+            store.insert(42, coord).unwrap();
+            store
+                .insert(7, FixedCoordinate::new(0.0, 0.0).unwrap())
+                .unwrap();
+            store.remove(7);
+            store.flush().unwrap();
+        }
+        let store = FlatNodeStore::open(&path, 200).unwrap();
+        assert_eq!(store.get(42), Some(coord));
+        assert_eq!(store.get(7), None);
+        assert_eq!(store.len(), 1);
+        store.insert(150, coord).unwrap();
+        assert_eq!(FlatNodeStore::create(&path, 100).unwrap().get(42), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn flat_store_keeps_the_replication_sequence() {
+        let path = env::temp_dir().join(format!(
+            "nominatim_rs-test-{}-sequence.flat",
+            std::process::id()
+        ));
+        let coord = FixedCoordinate::new(47.4, 8.5).unwrap();
+        {
+            let store = FlatNodeStore::create(&path, 100).unwrap();
+            assert_eq!(store.replication_sequence(), None);
+            assert!(store.insert(0, coord).is_err());
+            store.insert(1, coord).unwrap();
+            store.set_replication_sequence(0).unwrap();
+            assert_eq!(store.replication_sequence(), Some(0));
+            store.set_replication_sequence(4711).unwrap();
+        }
+        let store = FlatNodeStore::open(&path, 100).unwrap();
+        assert_eq!(store.replication_sequence(), Some(4711));
+        assert_eq!(store.get(0), None);
+        assert_eq!(store.len(), 1);
+        // A new store holds a new import.
+        let store = FlatNodeStore::create(&path, 100).unwrap();
+        assert_eq!(store.replication_sequence(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Writes a file through `write` into a temporary file next to `path` and
+/// renames it to `path` once it is on disk, so a crash leaves either the old
+/// or the new file behind, never half of one.
+pub fn write_atomic<P, F>(path: P, write: F) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Writes `header` and then `value` with bincode, so `read_matching` can
+/// compare the header without reading the whole value, see `write_atomic`.
+pub fn write_with_header<P, H, T>(path: P, header: &H, value: &T) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    H: Serialize,
+    T: Serialize,
+{
+    write_atomic(path, |writer| {
+        bincode::serialize_into(&mut *writer, header).map_err(io::Error::other)?;
+        bincode::serialize_into(writer, value).map_err(io::Error::other)
+    })
+}
+
+/// Reads a value written by `write_with_header` if it was written with
+/// `header`, `None` if it was written with another one.
+pub fn read_matching<P, H, T>(path: P, header: &H) -> io::Result<Option<T>>
+where
+    P: AsRef<Path>,
+    H: DeserializeOwned + PartialEq,
+    T: DeserializeOwned,
+{
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut reader = BufReader::new(File::open(path)?);
+    let written: H = bincode::deserialize_from(&mut reader).map_err(invalid)?;
+    if written != *header {
+        return Ok(None);
+    }
+    bincode::deserialize_from(reader).map(Some).map_err(invalid)
+}
+
+/// The path, size and modification time of a file, which change when the
+/// file is replaced or written to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub path: String,
+    pub size: u64,
+    pub modified_nanos: u128,
+}
+
+impl FileStamp {
+    /// Stamps the file at `path`, with its canonical path so the same file
+    /// given as a relative and an absolute path has the same stamp.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = fs::canonicalize(path)?;
+        let metadata = fs::metadata(&path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?;
+        Ok(FileStamp {
+            path: path.to_string_lossy().into_owned(),
+            size: metadata.len(),
+            modified_nanos: modified.as_nanos(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn values_round_trip_and_replace_the_old_file() {
+        let path = env::temp_dir().join(format!("nominatim_rs-test-{}-persist", process::id()));
+        write_with_header(&path, &(), &vec![(1u64, "one".to_string())]).unwrap();
+        write_with_header(&path, &(), &vec![(2u64, "two".to_string())]).unwrap();
+        let value: Option<Vec<(u64, String)>> = read_matching(&path, &()).unwrap();
+        assert_eq!(value, Some(vec![(2, "two".to_string())]));
+
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        assert!(!Path::new(&tmp).exists());
+
+        fs::write(&path, b"\xff").unwrap();
+        let error = read_matching::<_, u32, Vec<(u64, String)>>(&path, &1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn values_are_only_read_with_the_same_header() {
+        let path = env::temp_dir().join(format!("nominatim_rs-test-{}-header", process::id()));
+        write_with_header(&path, &Some(7u32), &vec![1u64, 2, 3]).unwrap();
+        let same: Option<Vec<u64>> = read_matching(&path, &Some(7u32)).unwrap();
+        assert_eq!(same, Some(vec![1, 2, 3]));
+        let other: Option<Vec<u64>> = read_matching(&path, &Some(8u32)).unwrap();
+        assert_eq!(other, None);
+        let none: Option<Vec<u64>> = read_matching(&path, &None::<u32>).unwrap();
+        assert_eq!(none, None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stamps_change_with_the_file() {
+        let path = env::temp_dir().join(format!("nominatim_rs-test-{}-stamp", process::id()));
+        fs::write(&path, b"one").unwrap();
+        let stamp = FileStamp::new(&path).unwrap();
+        assert_eq!(stamp.size, 3);
+        assert_eq!(FileStamp::new(&path).unwrap(), stamp);
+
+        fs::write(&path, b"three").unwrap();
+        assert_ne!(FileStamp::new(&path).unwrap(), stamp);
+        fs::remove_file(&path).unwrap();
+        assert!(FileStamp::new(&path).is_err());
+    }
+}
@@ -0,0 +1,450 @@
+use crate::location::FixedCoordinate;
+use crate::node::{Coordinate, NodeTags};
+use crate::relation::DebugRelation;
+use crate::style::OsmType;
+use crate::way::{Area, CoordsShape, DebugWay};
+use osm_pbf_iter::RelationMemberType;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// The id of an OSM object. Ids are signed: files saved by JOSM use negative
+/// ids for objects that were never uploaded. `osm_pbf_iter` hands ids out as
+/// `u64`, casting them back with `as i64` restores the sign.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum OsmId {
+    Node(i64),
+    Way(i64),
+    Relation(i64),
+}
+
+impl OsmId {
+    pub fn id(self) -> i64 {
+        match self {
+            OsmId::Node(id) | OsmId::Way(id) | OsmId::Relation(id) => id,
+        }
+    }
+
+    pub fn osm_type(self) -> OsmType {
+        match self {
+            OsmId::Node(_) => OsmType::Node,
+            OsmId::Way(_) => OsmType::Way,
+            OsmId::Relation(_) => OsmType::Relation,
+        }
+    }
+
+    pub fn from_member(member_type: &RelationMemberType, id: i64) -> Self {
+        match member_type {
+            RelationMemberType::Node => OsmId::Node(id),
+            RelationMemberType::Way => OsmId::Way(id),
+            RelationMemberType::Relation => OsmId::Relation(id),
+        }
+    }
+}
+
+/// Formats like Nominatim's `osm_type` and `osm_id`, e.g. `N123` or `W-4`.
+impl Display for OsmId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OsmId::Node(id) => write!(f, "N{}", id),
+            OsmId::Way(id) => write!(f, "W{}", id),
+            OsmId::Relation(id) => write!(f, "R{}", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Coordinate),
+    Way(CoordsShape),
+    Area(Arc<Area>),
+}
+
+fn xy(c: &Coordinate) -> (f64, f64) {
+    (c.location.lon_f64(), c.location.lat_f64())
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+/// Distance in degrees from `p` to the segment from `a` to `b`.
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_2 = dx * dx + dy * dy;
+    let t = if length_2 == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_2).clamp(0.0, 1.0)
+    };
+    distance(p, (a.0 + t * dx, a.1 + t * dy))
+}
+
+/// The centroid of a ring and its area, from the shoelace formula.
+fn ring_centroid(ring: &[Coordinate]) -> ((f64, f64), f64) {
+    let (mut cx, mut cy, mut area) = (0.0, 0.0, 0.0);
+    for edge in ring.windows(2) {
+        let (a, b) = (xy(&edge[0]), xy(&edge[1]));
+        let cross = a.0 * b.1 - b.0 * a.1;
+        area += cross;
+        cx += (a.0 + b.0) * cross;
+        cy += (a.1 + b.1) * cross;
+    }
+    if area == 0.0 {
+        return (ring.first().map(xy).unwrap_or_default(), 0.0);
+    }
+    ((cx / (3.0 * area), cy / (3.0 * area)), (area / 2.0).abs())
+}
+
+impl Geometry {
+    /// The lines the geometry is made of: every ring of an area, or the
+    /// single point of a node.
+    fn lines(&self) -> Vec<&[Coordinate]> {
+        match self {
+            Geometry::Point(c) => vec![std::slice::from_ref(c)],
+            Geometry::Way(shape) => vec![shape.coords()],
+            Geometry::Area(area) => area
+                .0
+                .iter()
+                .flat_map(|p| std::iter::once(&p.outer).chain(p.inners.iter()))
+                .map(|ring| ring.coords())
+                .collect(),
+        }
+    }
+
+    /// Whether the location is inside the geometry. Only closed ways and
+    /// areas contain anything.
+    pub fn contains(&self, location: FixedCoordinate) -> bool {
+        let point = Coordinate::new(0, location);
+        match self {
+            Geometry::Point(_) | Geometry::Way(CoordsShape::Linear(_)) => false,
+            Geometry::Way(CoordsShape::Polygonal(ring)) => ring.contains(&point),
+            Geometry::Area(area) => area.0.iter().any(|polygon| {
+                polygon.outer.contains(&point) && !polygon.inners.iter().any(|i| i.contains(&point))
+            }),
+        }
+    }
+
+    /// Distance in degrees from the location to the geometry, 0 inside of
+    /// it. Like Nominatim, degrees of latitude and longitude count the same.
+    pub fn distance(&self, location: FixedCoordinate) -> f64 {
+        if self.contains(location) {
+            return 0.0;
+        }
+        let p = (location.lon_f64(), location.lat_f64());
+        let mut nearest = f64::INFINITY;
+        for line in self.lines() {
+            if let [c] = line {
+                nearest = nearest.min(distance(p, xy(c)));
+            }
+            for edge in line.windows(2) {
+                nearest = nearest.min(segment_distance(p, xy(&edge[0]), xy(&edge[1])));
+            }
+        }
+        nearest
+    }
+
+    /// A point that stands for the geometry: the node itself, the middle of
+    /// a line, or the centroid of an area (of its largest outer ring).
+    /// `None` for a geometry without coordinates, like a way whose nodes
+    /// are all missing.
+    pub fn centroid(&self) -> Option<FixedCoordinate> {
+        let first = self.lines().into_iter().flatten().next()?.location;
+        let (x, y) = match self {
+            Geometry::Point(c) => return Some(c.location),
+            Geometry::Way(CoordsShape::Linear(line)) => {
+                let length: f64 = line
+                    .coords
+                    .windows(2)
+                    .map(|e| distance(xy(&e[0]), xy(&e[1])))
+                    .sum();
+                let mut remaining = length / 2.0;
+                let mut middle = (first.lon_f64(), first.lat_f64());
+                for edge in line.coords.windows(2) {
+                    let (a, b) = (xy(&edge[0]), xy(&edge[1]));
+                    let step = distance(a, b);
+                    if step >= remaining && step > 0.0 {
+                        let t = remaining / step;
+                        middle = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+                        break;
+                    }
+                    remaining -= step;
+                    middle = b;
+                }
+                middle
+            }
+            Geometry::Way(CoordsShape::Polygonal(ring)) => ring_centroid(ring.coords()).0,
+            Geometry::Area(area) => {
+                area.0
+                    .iter()
+                    .map(|p| ring_centroid(p.outer.coords()))
+                    .fold(
+                        ((0.0, 0.0), -1.0),
+                        |best, c| if c.1 > best.1 { c } else { best },
+                    )
+                    .0
+            }
+        };
+        Some(FixedCoordinate::new(y, x).unwrap_or(first))
+    }
+}
+
+/// Keys that give a place its class, in order of precedence. The first one
+/// present decides, so a named `amenity` that is also a `building` is an
+/// amenity.
+const MAIN_KEYS: [&str; 22] = [
+    "place",
+    "boundary",
+    "amenity",
+    "shop",
+    "tourism",
+    "leisure",
+    "historic",
+    "office",
+    "craft",
+    "aeroway",
+    "aerialway",
+    "railway",
+    "highway",
+    "waterway",
+    "natural",
+    "landuse",
+    "man_made",
+    "military",
+    "power",
+    "public_transport",
+    "junction",
+    "building",
+];
+
+/// One searchable object: a node, way or relation with its tags and
+/// geometry, and the Nominatim class and type derived from its main tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    pub id: OsmId,
+    /// The Nominatim class and type, e.g. `("amenity", "cafe")`.
+    pub category: (String, String),
+    pub tags: NodeTags,
+    pub geometry: Geometry,
+}
+
+impl Place {
+    pub fn from_node(id: i64, coord: FixedCoordinate, tags: &NodeTags) -> Option<Self> {
+        Some(Place {
+            id: OsmId::Node(id),
+            category: category(tags)?,
+            tags: tags.clone(),
+            geometry: Geometry::Point(Coordinate::new(id, coord)),
+        })
+    }
+
+    pub fn from_way(way: &DebugWay) -> Option<Self> {
+        let tags = way.tags()?;
+        Some(Place {
+            id: OsmId::Way(way.id()),
+            category: category(tags)?,
+            tags: tags.clone(),
+            geometry: Geometry::Way(way.coords_shape().clone()),
+        })
+    }
+
+    /// Only relations with an assembled area have a geometry. Routes and
+    /// other relations are not places.
+    pub fn from_relation(relation: &DebugRelation) -> Option<Self> {
+        let tags = relation.tags()?;
+        Some(Place {
+            id: OsmId::Relation(relation.id()),
+            category: category(tags)?,
+            tags: tags.clone(),
+            geometry: Geometry::Area(relation.area()?.clone()),
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.tags.get("name").map(|n| n.as_str())
+    }
+
+    /// Nominatim's address rank: how detailed a part of an address the
+    /// place is, from 4 for countries over 16 for cities and 26 for streets
+    /// to 30 for houses and POIs. 0 for places that are never part of an
+    /// address, like land use areas or boundaries without an admin level.
+    pub fn address_rank(&self) -> u8 {
+        let (class, value) = (self.category.0.as_str(), self.category.1.as_str());
+        match class {
+            "place" => match value {
+                "continent" | "sea" => 2,
+                "country" => 4,
+                "state" | "province" => 8,
+                "region" => 10,
+                "county" | "district" => 12,
+                "municipality" => 14,
+                "city" => 16,
+                "borough" | "town" => 18,
+                "village" => 19,
+                "suburb" | "hamlet" => 20,
+                "quarter" => 22,
+                "neighbourhood" | "city_block" => 24,
+                "isolated_dwelling" | "farm" | "locality" | "square" => 25,
+                _ => 30,
+            },
+            "boundary" if value == "administrative" => {
+                match self
+                    .tags
+                    .get("admin_level")
+                    .and_then(|l| l.parse::<u8>().ok())
+                {
+                    Some(level @ 2..=12) => 2 * level,
+                    _ => 0,
+                }
+            }
+            "boundary" | "landuse" | "natural" | "waterway" => 0,
+            "highway" => match value {
+                "motorway" | "trunk" | "primary" | "secondary" | "tertiary" | "unclassified"
+                | "residential" | "living_street" | "pedestrian" | "road" | "track" => 26,
+                "service" | "footway" | "path" | "cycleway" | "steps" | "bridleway" => 27,
+                _ if value.ends_with("_link") => 27,
+                _ => 30,
+            },
+            _ => 30,
+        }
+    }
+}
+
+/// The class and type from the first main key with a usable value. Objects
+/// that only carry an address are `place=house`, like in Nominatim.
+fn category(tags: &NodeTags) -> Option<(String, String)> {
+    for key in MAIN_KEYS.iter() {
+        if let Some(value) = tags.get(*key) {
+            if value != "no" {
+                return Some((key.to_string(), value.clone()));
+            }
+        }
+    }
+
+    if tags.contains_key("addr:housenumber") {
+        return Some(("place".to_string(), "house".to_string()));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::way::{ClosedLineString, LineString, Polygon};
+
+    fn coords(points: &[(f64, f64)]) -> Vec<Coordinate> {
+        points
+            .iter()
+            .enumerate()
+            .map(|(i, (lat, lon))| {
+                Coordinate::new(i as i64, FixedCoordinate::new(*lat, *lon).unwrap())
+            })
+            .collect()
+    }
+
+    fn at(lat: f64, lon: f64) -> Option<FixedCoordinate> {
+        Some(FixedCoordinate::new(lat, lon).unwrap())
+    }
+
+    #[test]
+    fn centroid_of_lines_and_areas() {
+        let line = Geometry::Way(CoordsShape::Linear(LineString {
+            coords: coords(&[(0.0, 0.0), (0.0, 1.0), (0.0, 3.0)]),
+        }));
+        assert_eq!(line.centroid(), at(0.0, 1.5));
+
+        let square =
+            ClosedLineString::new(coords(&[(0.0, 0.0), (0.0, 2.0), (2.0, 2.0), (2.0, 0.0)]))
+                .unwrap();
+        assert_eq!(
+            Geometry::Way(CoordsShape::Polygonal(square.clone())).centroid(),
+            at(1.0, 1.0)
+        );
+
+        let small = ClosedLineString::new(coords(&[(5.0, 5.0), (5.0, 6.0), (6.0, 6.0)])).unwrap();
+        let area = Geometry::Area(Arc::new(Area(vec![
+            Polygon {
+                outer: small,
+                inners: Vec::new(),
+            },
+            Polygon {
+                outer: square,
+                inners: Vec::new(),
+            },
+        ])));
+        assert_eq!(area.centroid(), at(1.0, 1.0));
+    }
+
+    #[test]
+    fn empty_geometries_have_no_centroid() {
+        let line = Geometry::Way(CoordsShape::Linear(LineString { coords: Vec::new() }));
+        assert_eq!(line.centroid(), None);
+        assert_eq!(line.distance(FixedCoordinate::default()), f64::INFINITY);
+        let area = Geometry::Area(Arc::new(Area(Vec::new())));
+        assert_eq!(area.centroid(), None);
+        assert!(!area.contains(FixedCoordinate::default()));
+    }
+
+    #[test]
+    fn osm_ids_keep_their_sign() {
+        let josm = OsmId::Way(-4);
+        assert_eq!(josm.to_string(), "W-4");
+        assert_eq!(josm.id(), -4);
+        assert_eq!(josm.osm_type(), OsmType::Way);
+        assert_eq!(OsmId::Node(123).to_string(), "N123");
+        assert_eq!(OsmId::Relation(7).to_string(), "R7");
+        // As osm_pbf_iter hands the id out.
+        assert_eq!(
+            OsmId::from_member(&RelationMemberType::Node, -2i64 as u64 as i64),
+            OsmId::Node(-2)
+        );
+        assert_eq!(
+            OsmId::from_member(&RelationMemberType::Relation, 5),
+            OsmId::Relation(5)
+        );
+        assert_ne!(OsmId::Node(1), OsmId::Way(1));
+    }
+
+    fn tags(tags: &[(&str, &str)]) -> NodeTags {
+        tags.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn node(node_tags: &[(&str, &str)]) -> Option<Place> {
+        Place::from_node(
+            -1,
+            FixedCoordinate::new(47.0, 8.0).unwrap(),
+            &tags(node_tags),
+        )
+    }
+
+    #[test]
+    fn the_main_tag_decides_the_category_and_rank() {
+        let cafe = node(&[
+            ("amenity", "cafe"),
+            ("building", "yes"),
+            ("name", "Zentral"),
+        ])
+        .unwrap();
+        assert_eq!(cafe.id, OsmId::Node(-1));
+        assert_eq!(cafe.category, ("amenity".to_string(), "cafe".to_string()));
+        assert_eq!(cafe.name(), Some("Zentral"));
+        assert_eq!(cafe.address_rank(), 30);
+
+        let house = node(&[("addr:housenumber", "3"), ("building", "no")]).unwrap();
+        assert_eq!(house.category, ("place".to_string(), "house".to_string()));
+
+        let city = node(&[("place", "city")]).unwrap();
+        assert_eq!(city.address_rank(), 16);
+        let state = node(&[("boundary", "administrative"), ("admin_level", "4")]).unwrap();
+        assert_eq!(state.address_rank(), 8);
+        let unknown = node(&[("boundary", "administrative"), ("admin_level", "x")]).unwrap();
+        assert_eq!(unknown.address_rank(), 0);
+        let street = node(&[("highway", "residential")]).unwrap();
+        assert_eq!(street.address_rank(), 26);
+
+        assert_eq!(node(&[("name", "Nothing")]), None);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::multipolygon::{assemble_area, is_area_relation, RingAssemblyError};
+use crate::node::NodeTags;
+use crate::place::OsmId;
+use crate::style::{OsmType, Style};
+use crate::way::{Area, WayDB};
+use dashmap::DashMap;
+use osm_pbf_iter::{Relation, RelationMemberType};
+use std::sync::Arc;
+
+pub type RelationDB = DashMap<i64, DebugRelation>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelationMember {
+    pub id: i64,
+    pub role: String,
+    pub member_type: RelationMemberType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugRelation {
+    id: i64,
+    members: Vec<RelationMember>,
+    tags: Option<NodeTags>,
+    /// Shared with the places made from the relation, which would copy
+    /// the whole geometry otherwise.
+    area: Option<Arc<Area>>,
+}
+
+impl DebugRelation {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn members(&self) -> &[RelationMember] {
+        &self.members
+    }
+
+    pub fn member_ids(&self) -> impl Iterator<Item = OsmId> + '_ {
+        self.members
+            .iter()
+            .map(|m| OsmId::from_member(&m.member_type, m.id))
+    }
+
+    pub fn tags(&self) -> Option<&NodeTags> {
+        self.tags.as_ref()
+    }
+
+    pub fn area(&self) -> Option<&Arc<Area>> {
+        self.area.as_ref()
+    }
+
+    /// Assembles the area again from the current member ways, after some
+    /// of them changed. Relations that are not areas are left alone and
+    /// return `Ok(false)`.
+    pub fn rebuild_area(&mut self, way_db: &WayDB) -> Result<bool, RingAssemblyError> {
+        if !is_area_relation(self.tags.as_ref().and_then(|t| t.get("type"))) {
+            return Ok(false);
+        }
+        match assemble_area(&self.members, way_db) {
+            Ok(area) => {
+                self.area = Some(Arc::new(area));
+                Ok(true)
+            }
+            Err(e) => {
+                self.area = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl PartialOrd for DebugRelation {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.id.partial_cmp(&other.id)
+    }
+}
+
+pub fn process_relation<'a>(
+    relation: &'a Relation<'a>,
+    style: &Style,
+    way_db: &WayDB,
+    relation_db: &RelationDB,
+) -> Result<(), RingAssemblyError> {
+    let tags: NodeTags = style.filter_tags(OsmType::Relation, relation.tags());
+
+    let members: Vec<RelationMember> = relation
+        .members()
+        .map(|(role, id, member_type)| RelationMember {
+            id: id as i64,
+            role: String::from(role),
+            member_type,
+        })
+        .collect();
+
+    let mut result = Ok(());
+    let mut area = None;
+
+    if is_area_relation(tags.get("type")) {
+        match assemble_area(&members, way_db) {
+            Ok(a) => area = Some(Arc::new(a)),
+            Err(e) => result = Err(e),
+        }
+    }
+
+    let id = relation.id as i64;
+    relation_db.insert(
+        id,
+        DebugRelation {
+            id,
+            members,
+            tags: if tags.is_empty() { None } else { Some(tags) },
+            area,
+        },
+    );
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::encode::Member;
+    use crate::input::{encode_block, Element};
+    use crate::location::FixedCoordinate;
+    use crate::node::NodeCoordDB;
+    use crate::node_store::NodeLocationStore;
+    use crate::way::{process_way, RoadsDB};
+    use osm_pbf_iter::{Primitive, PrimitiveBlock};
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Runs the ways and then the relations through the import, on a square
+    /// of nodes 1 to 4.
+    fn import(
+        ways: &[Element],
+        relations: &[Element],
+    ) -> (RelationDB, Vec<Result<(), RingAssemblyError>>) {
+        let node_store = NodeCoordDB::default();
+        for (id, lat, lon) in [
+            (1, 47.0, 8.0),
+            (2, 47.0, 8.1),
+            (3, 47.1, 8.1),
+            (4, 47.1, 8.0),
+        ] {
+            NodeLocationStore::insert(&node_store, id, FixedCoordinate::new(lat, lon).unwrap())
+                .unwrap();
+        }
+        let style = Style::default();
+        let (way_db, relation_db) = (WayDB::default(), RelationDB::default());
+        let data = encode_block(ways);
+        for primitive in PrimitiveBlock::parse(&data).primitives() {
+            if let Primitive::Way(way) = primitive {
+                process_way(&way, &style, &node_store, &RoadsDB::default(), &way_db).unwrap();
+            }
+        }
+        let data = encode_block(relations);
+        let results = PrimitiveBlock::parse(&data)
+            .primitives()
+            .filter_map(|primitive| match primitive {
+                Primitive::Relation(relation) => {
+                    Some(process_relation(&relation, &style, &way_db, &relation_db))
+                }
+                _ => None,
+            })
+            .collect();
+        (relation_db, results)
+    }
+
+    fn relation(id: i64, ways: &[i64], relation_tags: &[(&str, &str)]) -> Element {
+        Element::Relation {
+            id,
+            members: ways
+                .iter()
+                .map(|&id| Member {
+                    id,
+                    member_type: RelationMemberType::Way,
+                    role: "outer".to_string(),
+                })
+                .collect(),
+            info: Default::default(),
+            tags: tags(relation_tags),
+        }
+    }
+
+    fn way(id: i64, refs: Vec<i64>) -> Element {
+        Element::Way {
+            id,
+            refs,
+            info: Default::default(),
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn multipolygons_get_an_area() {
+        let (relations, results) = import(
+            &[way(10, vec![1, 2, 3]), way(11, vec![3, 4, 1])],
+            &[relation(
+                20,
+                &[10, 11],
+                &[
+                    ("type", "multipolygon"),
+                    ("landuse", "forest"),
+                    ("source", "survey"),
+                ],
+            )],
+        );
+        assert!(results[0].is_ok());
+        let forest = relations.get(&20).unwrap();
+        assert_eq!(
+            forest.member_ids().collect::<Vec<_>>(),
+            vec![OsmId::Way(10), OsmId::Way(11)]
+        );
+        assert_eq!(forest.members()[0].role, "outer");
+        // The style drops `source`.
+        assert_eq!(forest.tags().unwrap().len(), 2);
+        let area = forest.area().unwrap();
+        assert_eq!(area.0.len(), 1);
+        assert_eq!(area.0[0].outer.coords().len(), 5);
+    }
+
+    #[test]
+    fn other_relations_are_stored_without_an_area() {
+        let (relations, results) = import(
+            &[way(10, vec![1, 2, 3])],
+            &[relation(21, &[10], &[("type", "route"), ("route", "bus")])],
+        );
+        assert!(results[0].is_ok());
+        let route = relations.get(&21).unwrap();
+        assert!(route.tags().is_some());
+        assert!(route.area().is_none());
+    }
+
+    #[test]
+    fn open_rings_are_stored_with_an_error() {
+        let (relations, results) = import(
+            &[way(10, vec![1, 2, 3])],
+            &[relation(
+                22,
+                &[10],
+                &[("type", "multipolygon"), ("natural", "water")],
+            )],
+        );
+        assert!(results[0].is_err());
+        assert!(relations.get(&22).unwrap().area().is_none());
+    }
+}
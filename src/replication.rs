@@ -0,0 +1,494 @@
+use crate::input::InputError;
+use crate::node_store::NodeLocationStore;
+use crate::report::ChangeReport;
+use crate::style::Style;
+use crate::update::{apply_change, Stores};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+/// How long to wait for a replication server to accept a connection or to
+/// send more of a response.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum ReplicationError {
+    Io(io::Error),
+    Input(InputError),
+    /// A `state.txt` without a valid `sequenceNumber`.
+    InvalidState(String),
+    /// The node store records an earlier diff than the imported data is at.
+    StateBehind {
+        recorded: u64,
+        imported: u64,
+    },
+}
+
+impl Display for ReplicationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ReplicationError::Io(e) => write!(f, "{}", e),
+            ReplicationError::Input(e) => write!(f, "{}", e),
+            ReplicationError::InvalidState(msg) => write!(f, "invalid state: {}", msg),
+            ReplicationError::StateBehind { recorded, imported } => write!(
+                f,
+                "the node store is at diff {}, but the imported data is at the later diff {}",
+                recorded, imported
+            ),
+        }
+    }
+}
+
+impl Error for ReplicationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReplicationError::Io(e) => Some(e),
+            ReplicationError::Input(e) => Some(e),
+            ReplicationError::InvalidState(_) | ReplicationError::StateBehind { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReplicationError {
+    fn from(e: io::Error) -> Self {
+        ReplicationError::Io(e)
+    }
+}
+
+impl From<InputError> for ReplicationError {
+    fn from(e: InputError) -> Self {
+        ReplicationError::Input(e)
+    }
+}
+
+/// The contents of a `state.txt`: the sequence number of a diff and the
+/// time the data was current.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicationState {
+    pub sequence: u64,
+    pub timestamp: Option<String>,
+}
+
+impl ReplicationState {
+    pub fn parse(data: &str) -> Result<Self, ReplicationError> {
+        let mut sequence = None;
+        let mut timestamp = None;
+        for line in data.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some(("sequenceNumber", n)) => {
+                    sequence = Some(n.trim().parse().map_err(|_| {
+                        ReplicationError::InvalidState(format!("sequenceNumber {}", n))
+                    })?)
+                }
+                // Colons are escaped in the Java properties format.
+                Some(("timestamp", t)) => timestamp = Some(t.trim().replace("\\:", ":")),
+                _ => (),
+            }
+        }
+        match sequence {
+            Some(sequence) => Ok(ReplicationState {
+                sequence,
+                timestamp,
+            }),
+            None => Err(ReplicationError::InvalidState(
+                "no sequenceNumber".to_string(),
+            )),
+        }
+    }
+
+    /// The state of an extract, from its header.
+    pub fn from_header(sequence: u64, timestamp: Option<i64>) -> Self {
+        ReplicationState {
+            sequence,
+            timestamp: timestamp.map(format_timestamp),
+        }
+    }
+}
+
+/// An ISO 8601 timestamp in UTC of seconds since the epoch, the way
+/// `state.txt` files have them.
+fn format_timestamp(seconds: i64) -> String {
+    let days = seconds.div_euclid(86_400);
+    let time = seconds.rem_euclid(86_400);
+    // Howard Hinnant's civil_from_days, with years starting in March.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Where the diffs of a replication directory live, for example
+/// `000/001/234.osc.gz` for sequence 1234.
+pub fn sequence_path(sequence: u64) -> String {
+    format!(
+        "{:03}/{:03}/{:03}",
+        sequence / 1_000_000,
+        sequence / 1000 % 1000,
+        sequence % 1000
+    )
+}
+
+/// A replication directory, either on disk or served over plain HTTP.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplicationSource {
+    Directory(PathBuf),
+    Http {
+        host: String,
+        port: u16,
+        path: String,
+    },
+}
+
+impl ReplicationSource {
+    /// Parses `http://host[:port]/path` or a directory path.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let rest = match source.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => return Ok(ReplicationSource::Directory(PathBuf::from(source))),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("Invalid port in {}.", source))?,
+            ),
+            None => (authority, 80),
+        };
+        Ok(ReplicationSource::Http {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Where a file below the replication directory is, for messages.
+    fn location(&self, name: &str) -> String {
+        match self {
+            ReplicationSource::Directory(dir) => dir.join(name).display().to_string(),
+            ReplicationSource::Http { host, port, path } => {
+                format!("http://{}:{}{}/{}", host, port, path, name)
+            }
+        }
+    }
+
+    /// Reads a file below the replication directory.
+    fn fetch(&self, name: &str) -> io::Result<Vec<u8>> {
+        match self {
+            ReplicationSource::Directory(dir) => fs::read(dir.join(name)),
+            ReplicationSource::Http { host, port, path } => {
+                http_get(host, *port, &format!("{}/{}", path, name))
+            }
+        }
+    }
+
+    /// The newest diff that is available.
+    pub fn state(&self) -> Result<ReplicationState, ReplicationError> {
+        let data = self.fetch("state.txt")?;
+        ReplicationState::parse(&String::from_utf8_lossy(&data))
+    }
+
+    /// A local path of the diff with the given sequence, downloading it
+    /// into the temporary directory first for HTTP sources. The download
+    /// never replaces or follows an existing file and only the owner can
+    /// read it.
+    fn diff(&self, sequence: u64) -> io::Result<PathBuf> {
+        let name = format!("{}.osc.gz", sequence_path(sequence));
+        match self {
+            ReplicationSource::Directory(dir) => Ok(dir.join(name)),
+            ReplicationSource::Http { .. } => {
+                let data = self.fetch(&name)?;
+                let path = env::temp_dir().join(format!(
+                    "nominatim_rs-{}-{}.osc.gz",
+                    process::id(),
+                    sequence
+                ));
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let mut file = options.open(&path)?;
+                if let Err(e) = file.write_all(&data) {
+                    fs::remove_file(&path).ok();
+                    return Err(e);
+                }
+                Ok(path)
+            }
+        }
+    }
+}
+
+/// A minimal HTTP/1.0 GET, enough for a replication directory served by a
+/// local web server. There is no TLS, chunked encoding or redirect support.
+fn http_get(host: &str, port: u16, path: &str) -> io::Result<Vec<u8>> {
+    let mut stream = connect(host, port)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    )?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid(format!("no HTTP header in response to {}", path)))?;
+    let header = String::from_utf8_lossy(&response[..end]);
+    let status = header
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| invalid(format!("no HTTP status in response to {}", path)))?;
+    match status {
+        200 => Ok(response[end + 4..].to_vec()),
+        404 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", path),
+        )),
+        _ => Err(invalid(format!("HTTP status {} for {}", status, path))),
+    }
+}
+
+/// Connects to the first address of `host` that accepts a connection within
+/// `HTTP_TIMEOUT`.
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, HTTP_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host))
+    }))
+}
+
+/// Applies the diffs of a replication source in order and records the last
+/// applied one in the node store, the only store that outlives an import.
+/// The other stores are built from the input files by every import, so a
+/// run always applies the diffs after the ones the files are at, also
+/// those an earlier run applied before it stopped.
+pub struct Replicator {
+    source: ReplicationSource,
+    /// The last diff applied to the stores of this run.
+    applied: u64,
+    /// The last diff recorded in the node store, never less than `applied`.
+    recorded: u64,
+}
+
+impl Replicator {
+    /// Starts replicating data that was imported at `imported`. A node
+    /// store that records an earlier diff holds locations older than the
+    /// import, so it is refused. One that records a later diff was kept
+    /// from a run that stopped partway, and the diffs up to it are applied
+    /// again to the stores that were built anew, without moving the
+    /// recorded diff back. Applying a diff to locations that already have
+    /// it leaves them unchanged.
+    pub fn start(
+        source: ReplicationSource,
+        node_store: &dyn NodeLocationStore,
+        imported: &ReplicationState,
+    ) -> Result<Self, ReplicationError> {
+        let recorded = match node_store.replication_sequence() {
+            Some(recorded) if recorded < imported.sequence => {
+                return Err(ReplicationError::StateBehind {
+                    recorded,
+                    imported: imported.sequence,
+                })
+            }
+            Some(recorded) => recorded,
+            None => {
+                node_store.set_replication_sequence(imported.sequence)?;
+                imported.sequence
+            }
+        };
+        Ok(Replicator {
+            source,
+            applied: imported.sequence,
+            recorded,
+        })
+    }
+
+    pub fn source(&self) -> &ReplicationSource {
+        &self.source
+    }
+
+    /// The last diff applied to the stores of this run.
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    /// Applies every diff newer than the applied one, one at a time. The
+    /// node store only records a diff after it was applied completely.
+    pub fn catch_up(
+        &mut self,
+        style: &Style,
+        stores: &Stores,
+    ) -> Result<Vec<ChangeReport>, ReplicationError> {
+        let newest = self.source.state()?;
+        let mut reports = Vec::new();
+        for sequence in self.applied + 1..=newest.sequence {
+            let path = self.source.diff(sequence)?;
+            let report = apply_change(&path, style, stores);
+            if let ReplicationSource::Http { .. } = self.source {
+                fs::remove_file(&path).ok();
+            }
+            let mut report = report?;
+            report.file = self
+                .source
+                .location(&format!("{}.osc.gz", sequence_path(sequence)));
+            reports.push(report);
+            self.applied = sequence;
+            if sequence > self.recorded {
+                stores.node_store.set_replication_sequence(sequence)?;
+                self.recorded = sequence;
+            }
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_store::FlatNodeStore;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("nominatim_rs-test-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn timestamps_are_iso_8601() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1_580_472_000), "2020-01-31T12:00:00Z");
+        assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn states_are_parsed() {
+        let state = ReplicationState::parse(
+            "#Sat Jan 01 00:00:00 UTC 2020\nsequenceNumber=4711\ntimestamp=2020-01-31T12\\:00\\:00Z\n",
+        )
+        .unwrap();
+        assert_eq!(
+            state,
+            ReplicationState::from_header(4711, Some(1_580_472_000))
+        );
+        assert!(ReplicationState::parse("timestamp=2020-01-31T12\\:00\\:00Z").is_err());
+    }
+
+    /// A replication directory with an empty diff for every sequence from
+    /// `first` to `last`.
+    fn replication_directory(name: &str, first: u64, last: u64) -> ReplicationSource {
+        let dir = temp_path(name);
+        for sequence in first..=last {
+            let path = dir.join(format!("{}.osc.gz", sequence_path(sequence)));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let mut gz = flate2::write::GzEncoder::new(
+                File::create(&path).unwrap(),
+                flate2::Compression::default(),
+            );
+            gz.write_all(b"<osmChange version=\"0.6\"></osmChange>")
+                .unwrap();
+            gz.finish().unwrap();
+        }
+        fs::write(dir.join("state.txt"), format!("sequenceNumber={}\n", last)).unwrap();
+        ReplicationSource::Directory(dir)
+    }
+
+    #[test]
+    fn start_records_the_imported_state() {
+        let path = temp_path("start.flat");
+        let store = FlatNodeStore::create(&path, 10).unwrap();
+        let source = ReplicationSource::Directory(temp_path("replication"));
+        let imported = ReplicationState::from_header(100, None);
+
+        let replicator = Replicator::start(source.clone(), &store, &imported).unwrap();
+        assert_eq!(replicator.applied(), 100);
+        assert_eq!(store.replication_sequence(), Some(100));
+
+        // A node store kept from data at an older diff.
+        let newer = ReplicationState::from_header(105, None);
+        match Replicator::start(source, &store, &newer) {
+            Err(ReplicationError::StateBehind { recorded, imported }) => {
+                assert_eq!((recorded, imported), (100, 105))
+            }
+            _ => panic!("a node store behind the import was accepted"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_stopped_run_is_resumed() {
+        let path = temp_path("resume.flat");
+        let node_store = FlatNodeStore::create(&path, 10).unwrap();
+        let source = replication_directory("resume", 101, 107);
+        let (node_tags, ways, roads, relations, reverse_index) = Default::default();
+        let stores = Stores {
+            node_store: &node_store,
+            node_tags: &node_tags,
+            ways: &ways,
+            roads: &roads,
+            relations: &relations,
+            reverse_index: &reverse_index,
+        };
+        // An earlier run got to diff 105 before it stopped.
+        node_store.set_replication_sequence(105).unwrap();
+
+        let imported = ReplicationState::from_header(100, None);
+        let mut replicator = Replicator::start(source.clone(), &node_store, &imported).unwrap();
+        assert_eq!(node_store.replication_sequence(), Some(105));
+
+        // The stores built by this run are at 100, so 101 to 105 are applied
+        // to them again before the new diffs.
+        let reports = replicator.catch_up(&Style::default(), &stores).unwrap();
+        assert_eq!(reports.len(), 7);
+        assert!(reports[0].file.ends_with("000/000/101.osc.gz"));
+        assert_eq!(replicator.applied(), 107);
+        assert_eq!(node_store.replication_sequence(), Some(107));
+        assert!(replicator
+            .catch_up(&Style::default(), &stores)
+            .unwrap()
+            .is_empty());
+
+        if let ReplicationSource::Directory(dir) = source {
+            fs::remove_dir_all(dir).unwrap();
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
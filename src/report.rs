@@ -0,0 +1,260 @@
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Sizes of the node tags that were kept.
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize)]
+pub struct DebugStats {
+    pub num_tags_objects: u64,
+    pub total_size: u64,
+    pub min_size: u64,
+    pub max_size: u64,
+}
+
+impl Default for DebugStats {
+    fn default() -> Self {
+        DebugStats {
+            num_tags_objects: 0,
+            total_size: 0,
+            min_size: 100,
+            max_size: 0,
+        }
+    }
+}
+
+impl DebugStats {
+    pub fn add(&mut self, size: u64) {
+        self.total_size += size;
+        self.num_tags_objects += 1;
+        self.min_size = min(self.min_size, size);
+        self.max_size = max(self.max_size, size);
+    }
+
+    pub fn merge(&mut self, other: &DebugStats) {
+        self.num_tags_objects += other.num_tags_objects;
+        self.total_size += other.total_size;
+        self.min_size = min(self.min_size, other.min_size);
+        self.max_size = max(self.max_size, other.max_size);
+    }
+}
+
+/// Number of entries in each store at the end of a phase.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize)]
+pub struct StoreSizes {
+    pub node_coords: usize,
+    pub node_tags: usize,
+    pub ways: usize,
+    pub roads: usize,
+    pub relations: usize,
+}
+
+/// What one phase did with one file. Every phase handles one object type, so
+/// `processed` and `errors` are the per-type counts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PhaseReport {
+    pub phase: String,
+    pub seconds: f64,
+    pub bytes: u64,
+    /// Objects that were stored without errors.
+    pub processed: u64,
+    /// Objects that failed, by error kind.
+    pub errors: BTreeMap<String, u64>,
+    /// Objects skipped because an earlier file had the same or a newer
+    /// version. Only counted when merging.
+    pub duplicates: u64,
+    pub tags: DebugStats,
+    pub stores: StoreSizes,
+}
+
+impl PhaseReport {
+    pub fn error(&mut self, kind: &str) {
+        *self.errors.entry(kind.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// Adds the counts of a worker. Timings, bytes and store sizes are set
+    /// once for the whole phase.
+    pub fn merge(&mut self, other: &PhaseReport) {
+        self.processed += other.processed;
+        self.duplicates += other.duplicates;
+        for (kind, n) in other.errors.iter() {
+            *self.errors.entry(kind.clone()).or_insert(0) += n;
+        }
+        self.tags.merge(&other.tags);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FileReport {
+    pub file: String,
+    pub seconds: f64,
+    pub bytes: u64,
+    pub phases: Vec<PhaseReport>,
+}
+
+/// What applying one osmChange file did. Objects are counted by action,
+/// whether or not they could be stored.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChangeReport {
+    pub file: String,
+    pub seconds: f64,
+    pub created: u64,
+    pub modified: u64,
+    pub deleted: u64,
+    /// Ways that were not in the file, but whose geometry was rebuilt
+    /// because some of their nodes moved.
+    pub rebuilt_ways: u64,
+    /// Relations that were not in the file, but whose area was assembled
+    /// again because a member way changed or moved.
+    pub rebuilt_relations: u64,
+    pub errors: BTreeMap<String, u64>,
+    pub stores: StoreSizes,
+}
+
+impl ChangeReport {
+    pub fn error(&mut self, kind: &str) {
+        *self.errors.entry(kind.to_string()).or_insert(0) += 1;
+    }
+}
+
+/// Building the spatial or search index at the end of the import.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize)]
+pub struct IndexReport {
+    pub seconds: f64,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ImportReport {
+    pub seconds: f64,
+    pub files: Vec<FileReport>,
+    /// Every phase summed over all files, with the store sizes after the
+    /// phase ran on the last file.
+    pub combined: Vec<PhaseReport>,
+    /// The change files applied after the import, in order.
+    pub changes: Vec<ChangeReport>,
+    pub spatial_index: Option<IndexReport>,
+    /// `entries` is the number of different terms.
+    pub search_index: Option<IndexReport>,
+}
+
+impl ImportReport {
+    pub fn combine(&mut self) {
+        let mut combined: Vec<PhaseReport> = Vec::new();
+        for phase in self.files.iter().flat_map(|f| f.phases.iter()) {
+            let total = match combined.iter_mut().find(|p| p.phase == phase.phase) {
+                Some(total) => total,
+                None => {
+                    combined.push(PhaseReport {
+                        phase: phase.phase.clone(),
+                        ..PhaseReport::default()
+                    });
+                    combined.last_mut().unwrap()
+                }
+            };
+            total.merge(phase);
+            total.seconds += phase.seconds;
+            total.bytes += phase.bytes;
+            total.stores = phase.stores;
+        }
+
+        self.combined = combined;
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn phase(name: &str, processed: u64, errors: &[(&str, u64)], ways: usize) -> PhaseReport {
+        PhaseReport {
+            phase: name.to_string(),
+            seconds: 1.5,
+            bytes: 1000,
+            processed,
+            errors: errors.iter().map(|(k, n)| (k.to_string(), *n)).collect(),
+            duplicates: 1,
+            tags: DebugStats {
+                num_tags_objects: processed,
+                total_size: 10 * processed,
+                min_size: 10,
+                max_size: 10,
+            },
+            stores: StoreSizes {
+                ways,
+                ..StoreSizes::default()
+            },
+        }
+    }
+
+    fn report() -> ImportReport {
+        let file = |name: &str, nodes, ways| FileReport {
+            file: name.to_string(),
+            seconds: 3.0,
+            bytes: 2000,
+            phases: vec![
+                phase("nodes", nodes, &[("OutOfRange", 1)], 0),
+                phase("ways", ways, &[("MissingNode", 2)], ways as usize),
+            ],
+        };
+        let mut report = ImportReport {
+            files: vec![file("a.osm.pbf", 10, 4), file("b.osm.pbf", 20, 5)],
+            ..ImportReport::default()
+        };
+        report.combine();
+        report
+    }
+
+    #[test]
+    fn phases_are_summed_over_files() {
+        let report = report();
+        let phases: Vec<&str> = report.combined.iter().map(|p| p.phase.as_str()).collect();
+        assert_eq!(phases, vec!["nodes", "ways"]);
+
+        let ways = &report.combined[1];
+        assert_eq!(ways.seconds, 3.0);
+        assert_eq!(ways.bytes, 2000);
+        assert_eq!(ways.processed, 9);
+        assert_eq!(ways.duplicates, 2);
+        assert_eq!(ways.errors.get("MissingNode"), Some(&4));
+        assert_eq!(ways.error_count(), 4);
+        assert_eq!(ways.tags.num_tags_objects, 9);
+        assert_eq!(ways.tags.total_size, 90);
+        // The store sizes after the last file.
+        assert_eq!(ways.stores.ways, 5);
+
+        assert_eq!(report.combined[0].processed, 30);
+        assert_eq!(report.combined[0].errors.get("OutOfRange"), Some(&2));
+    }
+
+    #[test]
+    fn reports_are_written_as_json() {
+        let report = report();
+        let path = env::temp_dir().join(format!("nominatim_rs-test-{}-report.json", process::id()));
+        report.write_json(&path).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(json["files"][1]["file"], "b.osm.pbf");
+        assert_eq!(json["files"][1]["phases"][0]["processed"], 20);
+        assert_eq!(json["combined"][0]["phase"], "nodes");
+        assert_eq!(json["combined"][0]["processed"], 30);
+        assert_eq!(json["combined"][1]["errors"]["MissingNode"], 4);
+        assert_eq!(json["combined"][1]["stores"]["ways"], 5);
+        assert_eq!(json["combined"][1]["tags"]["total_size"], 90);
+        assert!(json["changes"].as_array().unwrap().is_empty());
+        assert!(json["spatial_index"].is_null());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::address::{address, areas_at, PlaceResult, STREET_DISTANCE};
+use crate::location::{FixedCoordinate, InvalidLocationError};
+use crate::place::Place;
+use crate::spatial::SpatialIndex;
+use crate::update::Stores;
+
+/// Nominatim's `REVERSE_MAX_RANKS`: the most detailed address rank a
+/// reverse lookup returns at each zoom level, from continents at zoom 0 to
+/// buildings at 18.
+const MAX_RANK_FOR_ZOOM: [u8; 19] = [
+    2, 2, 2, // continent, sea
+    4, 4, // country
+    8, // state
+    10, 10, // region
+    12, 12, // county
+    16, 17, // city
+    18, // town
+    19, // village, suburb
+    22, // hamlet, neighbourhood
+    25, // locality
+    26, // major streets
+    27, // minor streets
+    30, // buildings and POIs
+];
+
+/// How far a house or POI may be from the location to be found, in
+/// degrees. Further away the street is the better answer.
+const POI_DISTANCE: f64 = 0.001;
+
+pub fn max_rank_for_zoom(zoom: u8) -> u8 {
+    MAX_RANK_FOR_ZOOM[usize::from(zoom.min(18))]
+}
+
+/// The nearest street, house or POI up to `max_rank`. A house or POI wins
+/// over the street if it is at least as close.
+fn nearest_object(
+    location: FixedCoordinate,
+    max_rank: u8,
+    stores: &Stores,
+    index: &SpatialIndex,
+) -> Option<Place> {
+    let mut street: Option<(Place, f64)> = None;
+    let mut poi: Option<(Place, f64)> = None;
+    for entry in index.within(location.lat_f64(), location.lon_f64(), STREET_DISTANCE) {
+        let place = match stores.place(entry.id) {
+            Some(place) => place,
+            None => continue,
+        };
+        let rank = place.address_rank();
+        if rank < 26 || rank > max_rank {
+            continue;
+        }
+        let distance = place.geometry.distance(location);
+        let (best, limit) = if rank >= 28 {
+            (&mut poi, POI_DISTANCE)
+        } else {
+            (&mut street, STREET_DISTANCE)
+        };
+        if distance <= limit && best.as_ref().is_none_or(|(_, d)| distance < *d) {
+            *best = Some((place, distance));
+        }
+    }
+
+    match (street, poi) {
+        (Some((street, street_distance)), Some((poi, poi_distance))) => {
+            if poi_distance <= street_distance {
+                Some(poi)
+            } else {
+                Some(street)
+            }
+        }
+        (street, poi) => poi.or(street).map(|(place, _)| place),
+    }
+}
+
+/// Finds the place at a location, like Nominatim's `/reverse`. The zoom
+/// level decides how detailed the result is: at 18 the nearest house, POI
+/// or street, at lower zooms the smallest area containing the location
+/// that is not more detailed than the zoom asks for. `Ok(None)` if nothing
+/// is there.
+pub fn reverse(
+    lat: f64,
+    lon: f64,
+    zoom: u8,
+    stores: &Stores,
+    index: &SpatialIndex,
+) -> Result<Option<PlaceResult>, InvalidLocationError> {
+    let location = FixedCoordinate::new(lat, lon)?;
+    let max_rank = max_rank_for_zoom(zoom);
+
+    let mut place = None;
+    if max_rank >= 26 {
+        place = nearest_object(location, max_rank, stores, index);
+    }
+    if place.is_none() {
+        place = areas_at(location, max_rank.min(25) + 1, stores, index)
+            .into_iter()
+            .next()
+            .map(|(place, _)| place);
+    }
+
+    Ok(place.and_then(|place| {
+        let address = address(&place, stores, index);
+        PlaceResult::new(&place, address)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::tests::town;
+    use crate::update::tests::Data;
+
+    fn lookup(lat: f64, lon: f64, zoom: u8, data: &Data) -> Option<String> {
+        reverse(lat, lon, zoom, &data.stores(), &data.spatial_index())
+            .unwrap()
+            .map(|result| result.name.unwrap_or(result.display_name))
+    }
+
+    #[test]
+    fn zoom_levels_map_to_ranks() {
+        assert_eq!(max_rank_for_zoom(0), 2);
+        assert_eq!(max_rank_for_zoom(3), 4);
+        assert_eq!(max_rank_for_zoom(5), 8);
+        assert_eq!(max_rank_for_zoom(10), 16);
+        assert_eq!(max_rank_for_zoom(12), 18);
+        assert_eq!(max_rank_for_zoom(16), 26);
+        assert_eq!(max_rank_for_zoom(17), 27);
+        assert_eq!(max_rank_for_zoom(18), 30);
+        assert_eq!(max_rank_for_zoom(25), 30);
+    }
+
+    #[test]
+    fn zoom_cuts_off_the_more_detailed_ranks() {
+        let data = town();
+        // Right at the café.
+        assert_eq!(
+            lookup(47.0005, 8.0, 18, &data).as_deref(),
+            Some("Café Zentral")
+        );
+        assert_eq!(
+            lookup(47.0005, 8.0, 16, &data).as_deref(),
+            Some("Hauptstrasse")
+        );
+        assert_eq!(lookup(47.0005, 8.0, 14, &data).as_deref(), Some("Testdorf"));
+        assert_eq!(lookup(47.0005, 8.0, 10, &data).as_deref(), Some("Testdorf"));
+        assert_eq!(lookup(47.0005, 8.0, 9, &data).as_deref(), Some("Testland"));
+        assert_eq!(lookup(47.0005, 8.0, 3, &data).as_deref(), Some("Testland"));
+        // Nothing of that rank or less detailed is there.
+        assert_eq!(lookup(47.0005, 8.0, 0, &data), None);
+        assert_eq!(lookup(45.0, 8.0, 18, &data), None);
+        assert!(reverse(91.0, 8.0, 18, &data.stores(), &data.spatial_index()).is_err());
+    }
+
+    #[test]
+    fn a_nearer_street_beats_a_poi() {
+        let data = town();
+        // 0.0001 from the street and 0.0004 from the café.
+        assert_eq!(
+            lookup(47.0001, 8.0, 18, &data).as_deref(),
+            Some("Hauptstrasse")
+        );
+        // 0.0004 from the street and 0.0001 from the café.
+        assert_eq!(
+            lookup(47.0004, 8.0, 18, &data).as_deref(),
+            Some("Café Zentral")
+        );
+        // Further from the café than POIs are looked for, nearer to the street.
+        assert_eq!(
+            lookup(46.998, 8.0, 18, &data).as_deref(),
+            Some("Hauptstrasse")
+        );
+        // Outside of the street's reach only the areas are left.
+        assert_eq!(lookup(47.05, 8.05, 18, &data).as_deref(), Some("Testdorf"));
+    }
+}
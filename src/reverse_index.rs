@@ -0,0 +1,234 @@
+use crate::place::OsmId;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::collections::BTreeSet;
+use std::hash::Hash;
+
+/// The ways or relations an object is used by. Most nodes are in a single
+/// way, so that case is stored inline without an allocation.
+#[derive(Debug, Clone, PartialEq)]
+enum Parents {
+    One(i64),
+    /// Only empty while the entry of the last parent is being removed.
+    Many(Vec<i64>),
+}
+
+impl Parents {
+    fn as_slice(&self) -> &[i64] {
+        match self {
+            Parents::One(id) => std::slice::from_ref(id),
+            Parents::Many(ids) => ids,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+
+    fn insert(&mut self, parent: i64) {
+        match self {
+            Parents::One(id) if *id == parent => (),
+            Parents::One(id) => *self = Parents::Many(vec![*id, parent]),
+            Parents::Many(ids) if ids.is_empty() => *self = Parents::One(parent),
+            Parents::Many(ids) => {
+                if !ids.contains(&parent) {
+                    ids.push(parent);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, parent: i64) {
+        match self {
+            Parents::One(id) if *id == parent => *self = Parents::Many(Vec::new()),
+            Parents::One(_) => (),
+            Parents::Many(ids) => {
+                ids.retain(|id| *id != parent);
+                if let [id] = ids.as_slice() {
+                    *self = Parents::One(*id);
+                }
+            }
+        }
+    }
+}
+
+/// Both change the entry in place while holding its shard lock, so workers
+/// adding and removing parents of the same object at once do not lose any
+/// of the changes.
+fn add<K: Eq + Hash>(index: &DashMap<K, Parents>, child: K, parent: i64) {
+    index
+        .entry(child)
+        .and_modify(|parents| parents.insert(parent))
+        .or_insert(Parents::One(parent));
+}
+
+fn remove<K: Eq + Hash>(index: &DashMap<K, Parents>, child: K, parent: i64) {
+    if let Entry::Occupied(mut entry) = index.entry(child) {
+        entry.get_mut().remove(parent);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+    }
+}
+
+/// Everything that has to be rebuilt after some nodes moved.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Invalidated {
+    pub ways: BTreeSet<i64>,
+    /// Relations that have one of the nodes or ways as a member, and the
+    /// relations those are members of.
+    pub relations: BTreeSet<i64>,
+}
+
+/// Which ways use a node and which relations have an object as a member.
+/// Built during the import from the refs of every stored way and the
+/// members of every relation, and kept up to date by updates.
+#[derive(Debug, Default)]
+pub struct ReverseIndex {
+    node_ways: DashMap<i64, Parents>,
+    member_relations: DashMap<OsmId, Parents>,
+}
+
+impl ReverseIndex {
+    pub fn new() -> Self {
+        ReverseIndex::default()
+    }
+
+    pub fn add_way<I: IntoIterator<Item = i64>>(&self, way_id: i64, refs: I) {
+        for node_id in refs {
+            add(&self.node_ways, node_id, way_id);
+        }
+    }
+
+    pub fn remove_way<I: IntoIterator<Item = i64>>(&self, way_id: i64, refs: I) {
+        for node_id in refs {
+            remove(&self.node_ways, node_id, way_id);
+        }
+    }
+
+    pub fn add_relation<I: IntoIterator<Item = OsmId>>(&self, relation_id: i64, members: I) {
+        for member in members {
+            add(&self.member_relations, member, relation_id);
+        }
+    }
+
+    pub fn remove_relation<I: IntoIterator<Item = OsmId>>(&self, relation_id: i64, members: I) {
+        for member in members {
+            remove(&self.member_relations, member, relation_id);
+        }
+    }
+
+    /// The ways that use the node.
+    pub fn ways_of_node(&self, node_id: i64) -> Vec<i64> {
+        self.node_ways
+            .get(&node_id)
+            .map(|parents| parents.as_slice().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// The relations that have the object as a member.
+    pub fn relations_of(&self, member: OsmId) -> Vec<i64> {
+        self.member_relations
+            .get(&member)
+            .map(|parents| parents.as_slice().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// The relations that have the object as a member, directly or through
+    /// other relations.
+    pub fn invalidated_by_member(&self, member: OsmId) -> BTreeSet<i64> {
+        let mut relations = BTreeSet::new();
+        self.collect_parents(vec![member], &mut relations);
+        relations
+    }
+
+    /// The ways using any of the nodes, and the relations that contain the
+    /// nodes or those ways, directly or through other relations.
+    pub fn invalidated_by_nodes<I: IntoIterator<Item = i64>>(&self, nodes: I) -> Invalidated {
+        let mut invalidated = Invalidated::default();
+        let mut members = Vec::new();
+        for node_id in nodes {
+            members.push(OsmId::Node(node_id));
+            for way_id in self.ways_of_node(node_id) {
+                if invalidated.ways.insert(way_id) {
+                    members.push(OsmId::Way(way_id));
+                }
+            }
+        }
+        self.collect_parents(members, &mut invalidated.relations);
+        invalidated
+    }
+
+    /// Adds the parent relations of the members to `relations`, and theirs
+    /// in turn. Relations already in the set are not followed again, which
+    /// also ends cycles of relations that are members of each other.
+    fn collect_parents(&self, mut members: Vec<OsmId>, relations: &mut BTreeSet<i64>) {
+        while let Some(member) = members.pop() {
+            for relation_id in self.relations_of(member) {
+                if relations.insert(relation_id) {
+                    members.push(OsmId::Relation(relation_id));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn parents_are_added_and_removed() {
+        let index = ReverseIndex::new();
+        index.add_way(1, vec![10, 11]);
+        index.add_way(2, vec![11, 12]);
+        index.add_way(2, vec![11]);
+        assert_eq!(index.ways_of_node(11), vec![1, 2]);
+        index.remove_way(1, vec![10, 11]);
+        assert_eq!(index.ways_of_node(10), Vec::<i64>::new());
+        assert_eq!(index.ways_of_node(11), vec![2]);
+        index.remove_way(2, vec![11, 12]);
+        assert!(index.node_ways.is_empty());
+    }
+
+    #[test]
+    fn concurrent_changes_are_not_lost() {
+        let index = Arc::new(ReverseIndex::new());
+        // Way 0 stays, the other ways are added and removed again while
+        // the threads race on the same node.
+        index.add_way(0, vec![1]);
+        let workers: Vec<_> = (1..=8)
+            .map(|way| {
+                let index = index.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        index.add_way(way, vec![1]);
+                        index.remove_way(way, vec![1]);
+                    }
+                    index.add_way(way + 100, vec![1]);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let mut ways = index.ways_of_node(1);
+        ways.sort_unstable();
+        assert_eq!(ways, vec![0, 101, 102, 103, 104, 105, 106, 107, 108]);
+    }
+
+    #[test]
+    fn relations_of_relations_are_invalidated() {
+        let index = ReverseIndex::new();
+        index.add_relation(1, vec![OsmId::Way(5)]);
+        index.add_relation(2, vec![OsmId::Relation(1)]);
+        // A cycle does not loop forever.
+        index.add_relation(1, vec![OsmId::Relation(2)]);
+        assert_eq!(
+            index.invalidated_by_member(OsmId::Way(5)),
+            BTreeSet::from([1, 2])
+        );
+    }
+}
@@ -0,0 +1,667 @@
+use crate::address::{address, PlaceResult};
+use crate::location::str_to_coord;
+use crate::node_store::NodeLocationStore;
+use crate::place::{OsmId, Place};
+use crate::reverse::reverse;
+use crate::spatial::SpatialIndex;
+use crate::tokenizer::terms;
+use crate::update::Stores;
+use std::collections::{HashMap, HashSet};
+
+/// How many results a search returns at most.
+pub const SEARCH_LIMIT: usize = 10;
+
+/// Terms found in more places than this are too common to look for
+/// candidates with, like `street`. They still have to match.
+const MAX_POSTINGS: usize = 10_000;
+
+/// How many of the best ranked candidates of a free-text search get their
+/// address computed to check the terms that are not in the names or tags of
+/// the place itself.
+const MAX_CANDIDATES: usize = 1000;
+
+/// Keys whose values are names of the place. Keys starting with `name:`
+/// are names too.
+const NAME_KEYS: [&str; 7] = [
+    "name",
+    "alt_name",
+    "official_name",
+    "short_name",
+    "old_name",
+    "loc_name",
+    "ref",
+];
+
+/// The terms of all names of a place.
+pub fn name_terms(place: &Place) -> HashSet<String> {
+    place
+        .tags
+        .iter()
+        .filter(|(k, _)| NAME_KEYS.contains(&k.as_str()) || k.starts_with("name:"))
+        .flat_map(|(_, v)| terms(v))
+        .collect()
+}
+
+/// The terms of the `addr:*` tags of a place, e.g. its house number and
+/// street, and the `postal_code` of postcode boundaries.
+pub fn address_terms(place: &Place) -> HashSet<String> {
+    place
+        .tags
+        .iter()
+        .filter(|(k, _)| k.starts_with("addr:") || *k == "postal_code")
+        .flat_map(|(_, v)| terms(v))
+        .collect()
+}
+
+/// The terms of the values of some tags of a place.
+fn tag_terms(place: &Place, keys: &[&str]) -> HashSet<String> {
+    keys.iter()
+        .filter_map(|key| place.tags.get(*key))
+        .flat_map(|v| terms(v))
+        .collect()
+}
+
+/// Which places have a term in their names or `addr:*` tags, with the
+/// terms normalized by the tokenizer. Built from the stores at the end of
+/// the import and written next to the `SpatialIndex`, and loaded instead
+/// of built again like it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    postings: HashMap<String, Vec<OsmId>>,
+}
+
+impl SearchIndex {
+    pub fn build(stores: &Stores) -> Self {
+        let mut index = SearchIndex::default();
+        for node in stores.node_tags.iter() {
+            if let Some(location) = stores.node_store.get(*node.key()) {
+                if let Some(place) = Place::from_node(*node.key(), location, node.value()) {
+                    index.add(&place);
+                }
+            }
+        }
+        for way in stores.ways.iter() {
+            if let Some(place) = Place::from_way(way.value()) {
+                index.add(&place);
+            }
+        }
+        for relation in stores.relations.iter() {
+            if let Some(place) = Place::from_relation(relation.value()) {
+                index.add(&place);
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, place: &Place) {
+        let mut place_terms = name_terms(place);
+        place_terms.extend(address_terms(place));
+        for term in place_terms {
+            self.postings.entry(term).or_default().push(place.id);
+        }
+    }
+
+    /// Number of different terms.
+    pub fn len(&self) -> usize {
+        self.postings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.postings.is_empty()
+    }
+
+    pub fn postings(&self, term: &str) -> &[OsmId] {
+        self.postings.get(term).map(|p| p.as_slice()).unwrap_or(&[])
+    }
+
+    /// The places that have all of the terms in their names or tags.
+    pub fn places_with_all(&self, terms: &HashSet<String>) -> Vec<OsmId> {
+        let mut postings: Vec<&[OsmId]> = terms.iter().map(|term| self.postings(term)).collect();
+        postings.sort_by_key(|p| p.len());
+        let (first, rest) = match postings.split_first() {
+            Some(split) => split,
+            None => return Vec::new(),
+        };
+        let rest: Vec<HashSet<OsmId>> = rest.iter().map(|p| p.iter().copied().collect()).collect();
+        first
+            .iter()
+            .copied()
+            .filter(|id| rest.iter().all(|p| p.contains(id)))
+            .collect()
+    }
+}
+
+/// Nominatim's default importance for places without a Wikipedia link,
+/// from the rank: countries are more important than cities, and cities more
+/// than streets.
+fn importance(rank: u8) -> f64 {
+    let rank = if rank == 0 { 30 } else { rank };
+    0.75 - f64::from(rank) / 40.0
+}
+
+/// Looks up places by a free-text query like `10 Downing Street, London`,
+/// the way Nominatim's `/search` does. Every word of the query has to be
+/// in the names of the place, its `addr:*` tags or the places of its
+/// address. Results are ranked by how much of the query their names make
+/// up, how much of their names the query has and their importance. Queries
+/// that are a coordinate return the place there.
+pub fn search(
+    query: &str,
+    stores: &Stores,
+    spatial: &SpatialIndex,
+    index: &SearchIndex,
+) -> Vec<PlaceResult> {
+    if let Ok(coord) = str_to_coord(query) {
+        return reverse(coord.lat_f64(), coord.lon_f64(), 18, stores, spatial)
+            .ok()
+            .flatten()
+            .into_iter()
+            .collect();
+    }
+
+    let query_terms: HashSet<String> = terms(query).into_iter().collect();
+
+    let mut postings: Vec<&[OsmId]> = query_terms
+        .iter()
+        .map(|term| index.postings(term))
+        .filter(|p| !p.is_empty())
+        .collect();
+    postings.sort_by_key(|p| p.len());
+    if postings.is_empty() {
+        return Vec::new();
+    }
+    let rare = postings
+        .iter()
+        .take_while(|p| p.len() <= MAX_POSTINGS)
+        .count();
+
+    // How many of the terms each candidate has itself.
+    let mut candidates: HashMap<OsmId, usize> = HashMap::new();
+    for p in postings.iter().take(rare.max(1)) {
+        for id in p.iter() {
+            *candidates.entry(*id).or_insert(0) += 1;
+        }
+    }
+    // The score does not need the address, so the candidates are ranked by
+    // it before only the best ones get their address computed. Those with
+    // more of the terms themselves come first, they need less of the
+    // address to match.
+    let mut ranked: Vec<(usize, f64, Place, HashSet<String>)> = candidates
+        .into_iter()
+        .filter_map(|(id, count)| {
+            let place = stores.place(id)?;
+            let names = name_terms(&place);
+            let score = name_score(&query_terms, &names) + importance(place.address_rank());
+            Some((count, score, place, names))
+        })
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then(b.1.total_cmp(&a.1))
+            .then(a.2.id.cmp(&b.2.id))
+    });
+    ranked.truncate(MAX_CANDIDATES);
+
+    let mut results: Vec<(f64, PlaceResult)> = Vec::new();
+    for (_, score, place, names) in ranked {
+        let address = address(&place, stores, spatial);
+        let mut covered = address_terms(&place);
+        covered.extend(names);
+        covered.extend(address.iter().flat_map(|line| terms(&line.name)));
+        if !query_terms.iter().all(|term| covered.contains(term)) {
+            continue;
+        }
+        results.extend(PlaceResult::new(&place, address).map(|result| (score, result)));
+    }
+
+    best(results)
+}
+
+/// How much of the query the names make up plus how much of the names the
+/// query has, from 0 to 2.
+fn name_score(query_terms: &HashSet<String>, names: &HashSet<String>) -> f64 {
+    if query_terms.is_empty() || names.is_empty() {
+        return 0.0;
+    }
+    let in_name = query_terms.iter().filter(|t| names.contains(*t)).count();
+    let of_name = names.iter().filter(|t| query_terms.contains(*t)).count();
+    in_name as f64 / query_terms.len() as f64 + of_name as f64 / names.len() as f64
+}
+
+/// The results with the highest scores, up to `SEARCH_LIMIT`.
+fn best(mut results: Vec<(f64, PlaceResult)>) -> Vec<PlaceResult> {
+    results.sort_by(|a, b| b.0.total_cmp(&a.0));
+    // A place node and the boundary of the same place look the same.
+    let mut seen = HashSet::new();
+    results
+        .into_iter()
+        .map(|(_, result)| result)
+        .filter(|result| seen.insert(result.display_name.clone()))
+        .take(SEARCH_LIMIT)
+        .collect()
+}
+
+/// The parts of an address a structured search can be given, from the most
+/// detailed to the least.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AddressField {
+    /// A street, optionally with a house number, like `10 Downing Street`.
+    Street,
+    PostalCode,
+    City,
+    County,
+    State,
+    Country,
+}
+
+impl AddressField {
+    /// Whether a place is at the level of the address hierarchy the field
+    /// is for, e.g. a city is a `place=city`, `town` or `village`, or an
+    /// administrative boundary at admin level 7 or 8.
+    pub fn is_level_of(self, place: &Place) -> bool {
+        let place_type = place.tags.get("place").map(String::as_str);
+        let admin_level =
+            if place.tags.get("boundary").map(String::as_str) == Some("administrative") {
+                place
+                    .tags
+                    .get("admin_level")
+                    .and_then(|l| l.parse::<u8>().ok())
+            } else {
+                None
+            };
+        match self {
+            AddressField::Street => matches!(place.address_rank(), 26 | 27),
+            AddressField::PostalCode => {
+                place.tags.contains_key("addr:postcode") || place.tags.contains_key("postal_code")
+            }
+            AddressField::City => {
+                matches!(
+                    place_type,
+                    Some("city" | "town" | "village" | "hamlet" | "municipality")
+                ) || matches!(admin_level, Some(7 | 8))
+            }
+            AddressField::County => {
+                matches!(place_type, Some("county" | "district"))
+                    || matches!(admin_level, Some(5 | 6))
+            }
+            AddressField::State => {
+                matches!(place_type, Some("state" | "province" | "region"))
+                    || matches!(admin_level, Some(3 | 4))
+            }
+            AddressField::Country => place_type == Some("country") || admin_level == Some(2),
+        }
+    }
+
+    /// How well the place matches the terms given for the field, from 0 to
+    /// 1, or `None` if it does not. Streets match by their name, or with a
+    /// house number by the `addr:street` and `addr:housenumber` of a house.
+    /// Postcodes match by the `addr:postcode` or `postal_code` tag.
+    fn matches(self, place: &Place, field_terms: &HashSet<String>) -> Option<f64> {
+        if !self.is_level_of(place) && self != AddressField::Street {
+            return None;
+        }
+        let own = match self {
+            AddressField::Street if !self.is_level_of(place) => {
+                let housenumber = tag_terms(place, &["addr:housenumber"]);
+                if field_terms.is_disjoint(&housenumber) {
+                    return None;
+                }
+                let mut own = tag_terms(place, &["addr:street"]);
+                own.extend(housenumber);
+                own
+            }
+            AddressField::PostalCode => tag_terms(place, &["addr:postcode", "postal_code"]),
+            _ => name_terms(place),
+        };
+        if !field_terms.is_subset(&own) {
+            return None;
+        }
+        Some(field_terms.len() as f64 / own.len() as f64)
+    }
+
+    /// The key of address lines taken from `addr:*` tags for the field.
+    fn address_key(self) -> Option<&'static str> {
+        match self {
+            AddressField::Street => Some("road"),
+            AddressField::PostalCode => Some("postcode"),
+            AddressField::City => Some("city"),
+            _ => None,
+        }
+    }
+}
+
+/// A search by address parts, like Nominatim's structured `/search` with
+/// the `street`, `city`, `county`, `state`, `country` and `postalcode`
+/// parameters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StructuredQuery {
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub county: Option<String>,
+    pub state: Option<String>,
+    pub country: Option<String>,
+    pub postalcode: Option<String>,
+}
+
+impl StructuredQuery {
+    /// Parses URL query parameters like
+    /// `street=10+Hauptstrasse&city=Z%C3%BCrich`.
+    pub fn parse(params: &str) -> Result<Self, String> {
+        let mut query = StructuredQuery::default();
+        for param in params.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = Some(decode_param(value)?);
+            match key {
+                "street" => query.street = value,
+                "city" => query.city = value,
+                "county" => query.county = value,
+                "state" => query.state = value,
+                "country" => query.country = value,
+                "postalcode" => query.postalcode = value,
+                _ => return Err(format!("Unknown search parameter: {}.", key)),
+            }
+        }
+        Ok(query)
+    }
+
+    /// The fields that were given, from the most detailed to the least.
+    pub fn fields(&self) -> Vec<(AddressField, &str)> {
+        [
+            (AddressField::Street, &self.street),
+            (AddressField::PostalCode, &self.postalcode),
+            (AddressField::City, &self.city),
+            (AddressField::County, &self.county),
+            (AddressField::State, &self.state),
+            (AddressField::Country, &self.country),
+        ]
+        .iter()
+        .filter_map(|(field, value)| Some((*field, value.as_deref()?)))
+        .filter(|(_, value)| !value.trim().is_empty())
+        .collect()
+    }
+}
+
+/// Decodes `+` and `%XX` escapes of a URL query parameter.
+fn decode_param(value: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid escape in search parameter: {}.", value);
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).ok_or_else(invalid)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Looks up places by address parts. The results are at the level of the
+/// most detailed part that was given, and every other part has to be in
+/// their address at its own level: a `city` is only matched by the city the
+/// place is in, not by a street or county of the same name. Results are
+/// ranked by how much of their name the part makes up and their importance.
+pub fn structured_search(
+    query: &StructuredQuery,
+    stores: &Stores,
+    spatial: &SpatialIndex,
+    index: &SearchIndex,
+) -> Vec<PlaceResult> {
+    let fields: Vec<(AddressField, HashSet<String>)> = query
+        .fields()
+        .into_iter()
+        .map(|(field, value)| (field, terms(value).into_iter().collect()))
+        .collect();
+    let ((target, target_terms), outer) = match fields.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+
+    // The places at the right level for each of the other parts.
+    let outer: Vec<(AddressField, &HashSet<String>, HashSet<OsmId>)> = outer
+        .iter()
+        .map(|(field, field_terms)| {
+            let places = index
+                .places_with_all(field_terms)
+                .into_iter()
+                .filter(|id| {
+                    stores
+                        .place(*id)
+                        .is_some_and(|place| field.matches(&place, field_terms).is_some())
+                })
+                .collect();
+            (*field, field_terms, places)
+        })
+        .collect();
+
+    // Matching the target field and the score need no address, so all
+    // candidates are filtered and ranked first. Then the address is only
+    // computed until there are enough results, which are the best ones.
+    let mut candidates: Vec<(f64, Place)> = index
+        .places_with_all(target_terms)
+        .into_iter()
+        .filter_map(|id| {
+            let place = stores.place(id)?;
+            let name_match = target.matches(&place, target_terms)?;
+            Some((name_match + importance(place.address_rank()), place))
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.id.cmp(&b.1.id)));
+
+    let mut results: Vec<(f64, PlaceResult)> = Vec::new();
+    let mut found = HashSet::new();
+    for (score, place) in candidates {
+        if found.len() == SEARCH_LIMIT {
+            break;
+        }
+        let address = address(&place, stores, spatial);
+        let in_address = outer.iter().all(|(field, field_terms, places)| {
+            address.iter().any(|line| match line.osm_id {
+                Some(id) => places.contains(&id),
+                None => {
+                    field.address_key() == Some(line.key.as_str())
+                        && field_terms.is_subset(&terms(&line.name).into_iter().collect())
+                }
+            })
+        });
+        if !in_address {
+            continue;
+        }
+        if let Some(result) = PlaceResult::new(&place, address) {
+            found.insert(result.display_name.clone());
+            results.push((score, result));
+        }
+    }
+    best(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::FixedCoordinate;
+    use crate::node::{NodeCoordDB, NodeTagsDB};
+    use crate::relation::RelationDB;
+    use crate::reverse_index::ReverseIndex;
+    use crate::way::{RoadsDB, WayDB};
+
+    #[derive(Default)]
+    struct Data {
+        node_store: NodeCoordDB,
+        node_tags: NodeTagsDB,
+        ways: WayDB,
+        roads: RoadsDB,
+        relations: RelationDB,
+        reverse_index: ReverseIndex,
+    }
+
+    impl Data {
+        fn node(&self, id: i64, lat: f64, lon: f64, tags: &[(&str, &str)]) {
+            NodeLocationStore::insert(
+                &self.node_store,
+                id,
+                FixedCoordinate::new(lat, lon).unwrap(),
+            )
+            .unwrap();
+            self.node_tags.insert(
+                id,
+                tags.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            );
+        }
+
+        fn stores(&self) -> Stores<'_> {
+            Stores {
+                node_store: &self.node_store,
+                node_tags: &self.node_tags,
+                ways: &self.ways,
+                roads: &self.roads,
+                relations: &self.relations,
+                reverse_index: &self.reverse_index,
+            }
+        }
+
+        fn search(&self, query: &str) -> Vec<PlaceResult> {
+            let stores = self.stores();
+            let spatial = SpatialIndex::build(
+                stores.node_store,
+                stores.node_tags,
+                stores.ways,
+                stores.relations,
+            );
+            search(query, &stores, &spatial, &SearchIndex::build(&stores))
+        }
+
+        fn structured(&self, params: &str) -> Vec<PlaceResult> {
+            let stores = self.stores();
+            let spatial = SpatialIndex::build(
+                stores.node_store,
+                stores.node_tags,
+                stores.ways,
+                stores.relations,
+            );
+            let query = StructuredQuery::parse(params).unwrap();
+            structured_search(&query, &stores, &spatial, &SearchIndex::build(&stores))
+        }
+    }
+
+    fn town() -> Data {
+        let data = Data::default();
+        data.node(1, 47.001, 8.0, &[("place", "town"), ("name", "Testdorf")]);
+        data.node(
+            2,
+            47.0,
+            8.0,
+            &[
+                ("amenity", "cafe"),
+                ("name", "Café Zürich"),
+                ("addr:street", "Hauptstrasse"),
+                ("addr:housenumber", "10"),
+            ],
+        );
+        data.node(3, 47.3, 8.5, &[("place", "city"), ("name", "Zürich")]);
+        data
+    }
+
+    fn ids(results: &[PlaceResult]) -> Vec<i64> {
+        results.iter().map(|r| r.osm_id).collect()
+    }
+
+    #[test]
+    fn finds_places_by_normalized_names() {
+        let data = town();
+        assert_eq!(ids(&data.search("Café Zürich")), vec![2]);
+        assert_eq!(ids(&data.search("CAFE zurich")), vec![2]);
+        // The city's name is all of it, the café's only half.
+        assert_eq!(ids(&data.search("Zurich")), vec![3, 2]);
+        assert!(data.search("Bern").is_empty());
+    }
+
+    #[test]
+    fn every_term_has_to_match() {
+        let data = town();
+        // The town is in the address of the café, not of the city.
+        assert_eq!(ids(&data.search("Zürich, Testdorf")), vec![2]);
+        assert_eq!(ids(&data.search("10 Hauptstrasse")), vec![2]);
+        assert!(data.search("Zürich Bern").is_empty());
+        assert!(data.search("12 Hauptstrasse").is_empty());
+    }
+
+    #[test]
+    fn coordinates_are_looked_up() {
+        let data = town();
+        assert_eq!(ids(&data.search("47.0, 8.0")), vec![2]);
+        assert_eq!(ids(&data.search("47°0'N 8°0'E")), vec![2]);
+    }
+
+    #[test]
+    fn candidates_are_ranked_before_the_limit() {
+        let data = Data::default();
+        // More partial matches than are looked at, all with lower ids.
+        for id in 0..MAX_CANDIDATES as i64 + 1 {
+            let lon = 8.0 + id as f64 * 0.001;
+            data.node(
+                id,
+                47.0,
+                lon,
+                &[("amenity", "kiosk"), ("name", "Bahnhof Kiosk")],
+            );
+        }
+        data.node(
+            5000,
+            46.0,
+            7.0,
+            &[("railway", "station"), ("name", "Bahnhof")],
+        );
+        let results = data.search("Bahnhof");
+        assert_eq!(results[0].osm_id, 5000);
+    }
+
+    #[test]
+    fn structured_fields_match_at_their_level() {
+        let data = town();
+        data.node(4, 47.002, 8.0, &[("amenity", "bar"), ("name", "Testdorf")]);
+        assert_eq!(ids(&data.structured("city=Testdorf")), vec![1]);
+        assert_eq!(ids(&data.structured("city=Z%C3%BCrich")), vec![3]);
+        assert_eq!(
+            ids(&data.structured("street=10+Hauptstrasse&city=Testdorf")),
+            vec![2]
+        );
+        assert!(data
+            .structured("street=10+Hauptstrasse&city=Zurich")
+            .is_empty());
+        assert!(data.structured("street=12+Hauptstrasse").is_empty());
+        assert!(StructuredQuery::parse("town=Testdorf").is_err());
+    }
+
+    #[test]
+    fn structured_candidates_are_filtered_before_the_limit() {
+        let data = Data::default();
+        data.node(1, 47.0, 8.0, &[("place", "village"), ("name", "Adorf")]);
+        data.node(2, 46.0, 7.0, &[("place", "village"), ("name", "Bdorf")]);
+        // More houses on the street of the wrong village than are looked at.
+        for id in 10..2 * MAX_CANDIDATES as i64 {
+            let lon = 8.0 + (id % 10) as f64 * 0.001;
+            data.node(
+                id,
+                47.0,
+                lon,
+                &[("addr:street", "Hauptstrasse"), ("addr:housenumber", "1")],
+            );
+        }
+        data.node(
+            5000,
+            46.0,
+            7.0,
+            &[("addr:street", "Hauptstrasse"), ("addr:housenumber", "1")],
+        );
+        let results = data.structured("street=1+Hauptstrasse&city=Bdorf");
+        assert_eq!(ids(&results), vec![5000]);
+    }
+}
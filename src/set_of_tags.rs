@@ -1,152 +1,25 @@
 extern crate num_cpus;
 extern crate osm_pbf_iter;
-#[macro_use]
-extern crate lazy_static;
 
 use std::collections::BTreeSet;
 use std::env::args;
-use std::fs::File;
-use std::io::{BufReader, Seek, SeekFrom};
 use std::iter::FromIterator;
+use std::path::Path;
+use std::process::exit;
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
 
 use osm_pbf_iter::*;
 
-lazy_static! {
-    pub static ref POLYGON_KEYS: BTreeSet<&'static str> = BTreeSet::from_iter(vec![
-        "aeroway",
-        "amenity",
-        "building",
-        "harbour",
-        "historic",
-        "landuse",
-        "leisure",
-        "man_made",
-        "military",
-        "natural",
-        "office",
-        "place",
-        "power",
-        "public_transport",
-        "shop",
-        "sport",
-        "tourism",
-        "water",
-        "waterway",
-        "wetland",
-    ]);
-}
-
-lazy_static! {
-    pub static ref GENERIC_KEYS: BTreeSet<&'static str> = BTreeSet::from_iter(vec![
-        "access",
-        "addr:housename",
-        "addr:housenumber",
-        "addr:interpolation",
-        "admin_level",
-        "aerialway",
-        "aeroway",
-        "amenity",
-        "area",
-        "barrier",
-        "bicycle",
-        "boundary",
-        "brand",
-        "bridge",
-        "building",
-        "capital",
-        "construction",
-        "covered",
-        "culvert",
-        "cutting",
-        "denomination",
-        "disused",
-        "ele",
-        "embarkment",
-        "foot",
-        "generation:source",
-        "harbour",
-        "highway",
-        "historic",
-        "hours",
-        "intermittent",
-        "junction",
-        "landuse",
-        "layer",
-        "leisure",
-        "lock",
-        "man_made",
-        "military",
-        "motor_car",
-        "name",
-        "natural",
-        "office",
-        "oneway",
-        "operator",
-        "place",
-        "population",
-        "power",
-        "power_source",
-        "public_transport",
-        "railway",
-        "ref",
-        "religion",
-        "route",
-        "service",
-        "shop",
-        "sport",
-        "surface",
-        "toll",
-        "tourism",
-        "tower:type",
-        "tracktype",
-        "tunnel",
-        "type",
-        "water",
-        "waterway",
-        "wetland",
-        "width",
-        "wood",
-    ]);
-}
-
-lazy_static! {
-    pub static ref DELETE_TAGS: BTreeSet<&'static str> =
-        BTreeSet::from_iter(vec!["FIXME", "note", "source",]);
-}
+#[allow(dead_code)]
+mod input;
+use input::OsmReader;
 
-// Array used to specify z_order per key/value combination.
-// Each element has the form {key, value, z_order, is_road}.
-// If is_road=1, the object will be added to planet_osm_roads.
-lazy_static! {
-    pub static ref ZORDERING_TAGS: BTreeSet<(&'static str, &'static str, i8, i8)> =
-        BTreeSet::from_iter(vec![
-            ("railway", "nil", 5, 1),
-            ("boundary", "administrative", 0, 1),
-            ("bridge", "yes", 10, 0),
-            ("bridge", "true", 10, 0),
-            ("bridge", "1", 10, 0),
-            ("tunnel", "yes", -10, 0),
-            ("tunnel", "true", -10, 0),
-            ("tunnel", "1", -10, 0),
-            ("highway", "minor", 3, 0),
-            ("highway", "road", 3, 0),
-            ("highway", "unclassified", 3, 0),
-            ("highway", "residential", 3, 0),
-            ("highway", "tertiary_link", 4, 0),
-            ("highway", "tertiary", 4, 0),
-            ("highway", "secondary_link", 6, 1),
-            ("highway", "secondary", 6, 1),
-            ("highway", "primary_link", 7, 1),
-            ("highway", "primary", 7, 1),
-            ("highway", "trunk_link", 8, 1),
-            ("highway", "trunk", 8, 1),
-            ("highway", "motorway_link", 9, 1),
-            ("highway", "motorway", 9, 1),
-        ]);
-}
+#[allow(dead_code)]
+mod style;
+use style::{OsmType, Style};
 
 // fn blobs_worker(req_rx: Receiver<Blob>, res_tx: SyncSender<HashSet<String>>) {
 //     let mut tags_set: HashSet<String> = HashSet::new();
@@ -210,7 +83,10 @@ lazy_static! {
 //     return keyvalues, roads
 // }
 
-fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3], u64, u64)>) {
+// (avg intersection diff, empty counts, obj counts, tagged node count, empty node count)
+type Stats = (f64, [u64; 3], [u64; 3], u64, u64);
+
+fn blobs_worker(style: Arc<Style>, req_rx: Receiver<Blob>, res_tx: Sender<Stats>) {
     let mut diff: f64 = 0.0;
     // counts: [nodes, ways, rels];
     let mut empty_count: [u64; 3] = [0; 3];
@@ -218,20 +94,15 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
     let mut processed_node_tagged_count: u64 = 0;
     let mut processed_node_empty_count: u64 = 0;
 
-    loop {
-        let blob = match req_rx.recv() {
-            Ok(blob) => blob,
-            Err(_) => break,
-        };
-
+    while let Ok(blob) = req_rx.recv() {
         let data = blob.into_data();
         let primitive_block = PrimitiveBlock::parse(&data);
         for primitive in primitive_block.primitives() {
             match primitive {
                 Primitive::Node(x) => {
-                    if x.tags.len() < 1 {
+                    if x.tags.is_empty() {
                         empty_count[0] += 1;
-                        if obj_count[0] % 1_000_000 == 0 {
+                        if obj_count[0].is_multiple_of(1_000_000) {
                             println!("{:?}", x);
                         }
                         continue;
@@ -242,7 +113,9 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
                         tags.push(b);
                     });
                     for tag in tags.iter() {
-                        if (&POLYGON_KEYS).contains(tag) || (&GENERIC_KEYS).contains(tag) {
+                        if style.polygon_keys.contains(*tag)
+                            || style.tag_filter(OsmType::Node).keeps(tag)
+                        {
                             processed_node_tagged_count += 1;
                         }
                     }
@@ -263,12 +136,12 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
                     //     empty_count[0] += 1;
                     //     continue;
                     // }
-                    if tags.len() < 1 {
+                    if tags.is_empty() {
                         processed_node_empty_count += 1;
                         continue;
                     }
                     // diff += nlen / olen;
-                    if obj_count[0] % 1_000_000 == 0 {
+                    if obj_count[0].is_multiple_of(1_000_000) {
                         println!("Node: {:?}.", x,);
                     }
                     obj_count[0] += 1;
@@ -276,8 +149,9 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
                 Primitive::Way(x) => {
                     let set = BTreeSet::from_iter(x.tags().map(|x| x.0));
                     let olen: f64 = set.len() as f64;
-                    let intersection: Vec<&str> = (&set)
-                        .intersection(&GENERIC_KEYS)
+                    let intersection: Vec<&str> = set
+                        .iter()
+                        .filter(|k| style.tag_filter(OsmType::Way).keeps(k))
                         .cloned()
                         .collect::<Vec<&str>>();
                     let nlen: f64 = intersection.len() as f64;
@@ -286,7 +160,7 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
                         continue;
                     }
                     diff += nlen / olen;
-                    if obj_count[1] % 1_400_000 == 0 {
+                    if obj_count[1].is_multiple_of(1_400_000) {
                         println!(
                             "Way. Original tags: {:?}. Intersection tags: {:?}.",
                             set, intersection
@@ -298,15 +172,17 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
                 Primitive::Relation(x) => {
                     let set = BTreeSet::from_iter(x.tags().map(|x| x.0));
                     let olen: f64 = set.len() as f64;
-                    let intersection: Vec<&&str> =
-                        set.intersection(&GENERIC_KEYS).collect::<Vec<&&str>>();
+                    let intersection: Vec<&&str> = set
+                        .iter()
+                        .filter(|k| style.tag_filter(OsmType::Relation).keeps(k))
+                        .collect::<Vec<&&str>>();
                     let nlen: f64 = intersection.len() as f64;
                     if olen == 0_f64 || nlen == 0_f64 {
                         empty_count[2] += 1;
                         continue;
                     }
                     diff += nlen / olen;
-                    if obj_count[2] % 20_000 == 0 {
+                    if obj_count[2].is_multiple_of(20_000) {
                         println!(
                             "Rel. Original tags: {:?}. Intersection tags: {:?}.",
                             set, intersection
@@ -321,7 +197,7 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
 
     res_tx
         .send((
-            diff / (&mut obj_count.clone()).iter().sum::<u64>() as f64,
+            diff / obj_count.clone().iter().sum::<u64>() as f64,
             empty_count,
             obj_count,
             processed_node_tagged_count,
@@ -333,20 +209,33 @@ fn blobs_worker(req_rx: Receiver<Blob>, res_tx: Sender<(f64, [u64; 3], [u64; 3],
 fn main() {
     let cpus = num_cpus::get();
 
-    for arg in args().skip(1) {
+    let mut files: Vec<String> = args().skip(1).collect();
+    let style = match files.first() {
+        Some(a) if a.starts_with("--style=") => {
+            let path = files.remove(0)["--style=".len()..].to_string();
+            Style::from_file(Path::new(&path)).unwrap_or_else(|e| {
+                eprintln!("Could not load style {}: {}.", path, e);
+                exit(1);
+            })
+        }
+        _ => Style::default(),
+    };
+    let style = Arc::new(style);
+
+    for arg in files {
         let mut workers = Vec::with_capacity(cpus);
         for _ in 0..cpus {
             let (req_tx, req_rx) = channel();
             let (res_tx, res_rx) = channel();
             workers.push((req_tx, res_rx));
+            let style = style.clone();
             thread::spawn(move || {
-                blobs_worker(req_rx, res_tx);
+                blobs_worker(style, req_rx, res_tx);
             });
         }
 
         println!("Open {}", arg);
-        let f = File::open(&arg).unwrap();
-        let mut reader = BlobReader::new(BufReader::new(f));
+        let mut reader = OsmReader::open(&arg).unwrap();
         let start = Instant::now();
 
         let mut w = 0;
@@ -356,6 +245,11 @@ fn main() {
 
             req_tx.send(blob).unwrap();
         }
+        if let Some(e) = reader.take_error() {
+            eprintln!("Could not read {}: {}.", arg, e);
+            exit(1);
+        }
+
         let mut diff = 0.0_f64;
         let mut empty: [u64; 3] = [0; 3];
         let mut obj_counts: [u64; 3] = [0; 3];
@@ -374,24 +268,19 @@ fn main() {
             processed_node_tagged_count += worker_diff.3;
             processed_node_empty_count += worker_diff.4;
         }
-        diff = diff / cpus as f64;
+        diff /= cpus as f64;
 
         let stop = Instant::now();
         let duration = stop.duration_since(start);
         let duration = duration.as_secs() as f64 + (duration.subsec_nanos() as f64 / 1e9);
-        let mut f = reader.into_inner();
-        match f.seek(SeekFrom::Current(0)) {
-            Ok(pos) => {
-                let rate = pos as f64 / 1024.0 / 1024.0 / duration;
-                println!(
-                    "Processed {} MB in {:.2} seconds ({:.2} MB/s)",
-                    pos / 1024 / 1024,
-                    duration,
-                    rate
-                );
-            }
-            Err(_) => (),
-        }
+        let pos = reader.position();
+        let rate = pos as f64 / 1024.0 / 1024.0 / duration;
+        println!(
+            "Processed {} MB in {:.2} seconds ({:.2} MB/s)",
+            pos / 1024 / 1024,
+            duration,
+            rate
+        );
 
         let empty_to_tags_ratio: [f64; 3] = [
             empty[0] as f64 / obj_counts[0] as f64,
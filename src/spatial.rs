@@ -0,0 +1,193 @@
+use crate::address::place_node_radius;
+use crate::node::{Coordinate, NodeTagsDB};
+use crate::node_store::NodeLocationStore;
+use crate::place::{OsmId, Place};
+use crate::relation::RelationDB;
+use crate::way::WayDB;
+use rstar::{PointDistance, RTree, RTreeObject, AABB};
+
+/// A point or bounding box in degrees, with `lon` as x and `lat` as y.
+pub type Envelope = AABB<[f64; 2]>;
+
+/// One object in the spatial index: its id and the bounding box of its
+/// geometry, a single point for nodes.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpatialEntry {
+    pub id: OsmId,
+    pub envelope: Envelope,
+}
+
+impl RTreeObject for SpatialEntry {
+    type Envelope = Envelope;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+/// Squared distance in degrees to the bounding box, 0 inside of it. Good
+/// enough to find candidates, the distance to the real geometry may be
+/// larger.
+impl PointDistance for SpatialEntry {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        self.envelope.distance_2(point)
+    }
+}
+
+fn point(lat: f64, lon: f64) -> [f64; 2] {
+    [lon, lat]
+}
+
+fn envelope_of<'a, I: IntoIterator<Item = &'a Coordinate>>(coords: I) -> Option<Envelope> {
+    let points: Vec<[f64; 2]> = coords
+        .into_iter()
+        .map(|c| point(c.location.lat_f64(), c.location.lon_f64()))
+        .collect();
+    if points.is_empty() {
+        None
+    } else {
+        Some(AABB::from_points(points.iter()))
+    }
+}
+
+/// An R-tree over the tagged nodes, the tagged ways and the relations with
+/// an area, so objects can be found by location. It is bulk loaded once all
+/// objects are stored, which gives a better tree than inserting them one by
+/// one. It is written next to the node store and loaded instead of built
+/// again as long as the input files do not change.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SpatialIndex {
+    tree: RTree<SpatialEntry>,
+    /// Nodes with a `place` tag, which are looked up far from the location
+    /// of an address, in a tree of their own so that does not mean going
+    /// through all POIs in between. Their envelope is the box of
+    /// `place_node_radius` around them, so a lookup at a location finds the
+    /// places that reach it.
+    place_nodes: RTree<SpatialEntry>,
+}
+
+impl SpatialIndex {
+    pub fn build(
+        node_store: &dyn NodeLocationStore,
+        node_tags: &NodeTagsDB,
+        ways: &WayDB,
+        relations: &RelationDB,
+    ) -> Self {
+        let mut entries = Vec::new();
+        let mut place_nodes = Vec::new();
+        for node in node_tags.iter() {
+            if let Some(location) = node_store.get(*node.key()) {
+                let (lat, lon) = (location.lat_f64(), location.lon_f64());
+                let id = OsmId::Node(*node.key());
+                entries.push(SpatialEntry {
+                    id,
+                    envelope: AABB::from_point(point(lat, lon)),
+                });
+                if node.value().contains_key("place") {
+                    if let Some(place) = Place::from_node(*node.key(), location, node.value()) {
+                        let radius = place_node_radius(place.address_rank());
+                        place_nodes.push(SpatialEntry {
+                            id,
+                            envelope: AABB::from_corners(
+                                point(lat - radius, lon - radius),
+                                point(lat + radius, lon + radius),
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+        for way in ways.iter().filter(|w| w.tags().is_some()) {
+            if let Some(envelope) = envelope_of(way.coords_shape().coords()) {
+                entries.push(SpatialEntry {
+                    id: OsmId::Way(way.id()),
+                    envelope,
+                });
+            }
+        }
+        for relation in relations.iter() {
+            let area = match relation.area() {
+                Some(area) => area,
+                None => continue,
+            };
+            // The outer rings hold the inner ones.
+            if let Some(envelope) = envelope_of(area.0.iter().flat_map(|p| p.outer.coords())) {
+                entries.push(SpatialEntry {
+                    id: OsmId::Relation(relation.id()),
+                    envelope,
+                });
+            }
+        }
+
+        SpatialIndex {
+            tree: RTree::bulk_load(entries),
+            place_nodes: RTree::bulk_load(place_nodes),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.size() == 0
+    }
+
+    /// The objects whose bounding box is at most `distance` degrees from
+    /// the location, in no particular order.
+    pub fn within(&self, lat: f64, lon: f64, distance: f64) -> impl Iterator<Item = &SpatialEntry> {
+        self.tree
+            .locate_within_distance(point(lat, lon), distance * distance)
+    }
+
+    /// The nodes with a `place` tag whose `place_node_radius` box contains
+    /// the location.
+    pub fn place_nodes_at(&self, lat: f64, lon: f64) -> impl Iterator<Item = &SpatialEntry> {
+        self.place_nodes.locate_all_at_point(&point(lat, lon))
+    }
+
+    /// The objects whose bounding box contains the location.
+    pub fn at(&self, lat: f64, lon: f64) -> impl Iterator<Item = &SpatialEntry> {
+        self.tree.locate_all_at_point(&point(lat, lon))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::FixedCoordinate;
+    use crate::node::NodeCoordDB;
+
+    #[test]
+    fn place_nodes_are_found_within_their_radius() {
+        let node_store = NodeCoordDB::default();
+        let node_tags = NodeTagsDB::default();
+        for (id, place, lon) in [(1, "village", 8.0), (2, "city", 9.0)] {
+            NodeLocationStore::insert(&node_store, id, FixedCoordinate::new(47.0, lon).unwrap())
+                .unwrap();
+            node_tags.insert(id, [("place".to_string(), place.to_string())].into());
+        }
+        node_tags.insert(3, [("amenity".to_string(), "cafe".to_string())].into());
+        NodeLocationStore::insert(&node_store, 3, FixedCoordinate::new(47.0, 8.0).unwrap())
+            .unwrap();
+        let index = SpatialIndex::build(
+            &node_store,
+            &node_tags,
+            &WayDB::default(),
+            &RelationDB::default(),
+        );
+        assert_eq!(index.len(), 3);
+
+        let ids = |lat, lon| -> Vec<OsmId> {
+            let mut ids: Vec<OsmId> = index.place_nodes_at(lat, lon).map(|e| e.id).collect();
+            ids.sort();
+            ids
+        };
+        // Villages reach 0.04 degrees, cities 0.16.
+        assert_eq!(ids(47.0, 8.0), vec![OsmId::Node(1)]);
+        assert_eq!(ids(47.03, 8.0), vec![OsmId::Node(1)]);
+        assert_eq!(ids(47.05, 8.0), vec![]);
+        assert_eq!(ids(47.1, 9.1), vec![OsmId::Node(2)]);
+        assert_eq!(ids(47.0, 8.5), vec![]);
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The style used when no `--style` is given.
+pub const DEFAULT_STYLE: &str = include_str!("../styles/default.style");
+
+#[derive(Debug)]
+pub enum StyleError {
+    Io(io::Error),
+    /// A line that could not be parsed, with its line number.
+    Parse(usize, String),
+}
+
+impl Display for StyleError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            StyleError::Io(e) => write!(f, "{}", e),
+            StyleError::Parse(line, msg) => write!(f, "line {}: {}", line, msg),
+        }
+    }
+}
+
+impl Error for StyleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StyleError::Io(e) => Some(e),
+            StyleError::Parse(..) => None,
+        }
+    }
+}
+
+impl From<io::Error> for StyleError {
+    fn from(e: io::Error) -> Self {
+        StyleError::Io(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OsmType {
+    Node,
+    Way,
+    Relation,
+}
+
+/// Decides which tags of one object type are kept. A key is kept when a keep
+/// rule matches it and no delete rule does. Rules are exact keys or prefixes,
+/// written as `name:*` in the style file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagFilter {
+    keep: HashSet<String>,
+    keep_prefixes: Vec<String>,
+    delete: HashSet<String>,
+    delete_prefixes: Vec<String>,
+}
+
+impl TagFilter {
+    fn add(&mut self, key: &str, delete: bool) {
+        let (exact, prefixes) = if delete {
+            (&mut self.delete, &mut self.delete_prefixes)
+        } else {
+            (&mut self.keep, &mut self.keep_prefixes)
+        };
+        match key.strip_suffix('*') {
+            Some(prefix) => prefixes.push(prefix.to_string()),
+            None => {
+                exact.insert(key.to_string());
+            }
+        }
+    }
+
+    pub fn keeps(&self, key: &str) -> bool {
+        let matches = |exact: &HashSet<String>, prefixes: &[String]| {
+            exact.contains(key) || prefixes.iter().any(|p| key.starts_with(p.as_str()))
+        };
+        matches(&self.keep, &self.keep_prefixes) && !matches(&self.delete, &self.delete_prefixes)
+    }
+
+    pub fn filter<'a, I>(&self, tags: I) -> HashMap<String, String>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        tags.into_iter()
+            .filter(|(k, _v)| self.keeps(k))
+            .map(|(k, v)| (String::from(k), String::from(v)))
+            .collect()
+    }
+}
+
+/// A key/value combination that changes the z_order of a way. A `value` of
+/// `None` matches any value of the key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZOrderTag {
+    pub key: String,
+    pub value: Option<String>,
+    pub z_order: i8,
+    /// Ways with this tag are also added to the `ROADS_DB`.
+    pub is_road: bool,
+}
+
+/// Which tags are kept, which make a way an area and how ways are z-ordered,
+/// read from an osm2pgsql style file. See `styles/default.style` for the
+/// format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    node_tags: TagFilter,
+    way_tags: TagFilter,
+    relation_tags: TagFilter,
+    /// Keys that make a closed way an area, from the `polygon` flag. A key
+    /// last listed as `linear` is not one.
+    pub polygon_keys: HashSet<String>,
+    pub zordering_tags: Vec<ZOrderTag>,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style::parse(DEFAULT_STYLE).expect("The default style is valid.")
+    }
+}
+
+impl Style {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, StyleError> {
+        Style::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn tag_filter(&self, osm_type: OsmType) -> &TagFilter {
+        match osm_type {
+            OsmType::Node => &self.node_tags,
+            OsmType::Way => &self.way_tags,
+            OsmType::Relation => &self.relation_tags,
+        }
+    }
+
+    /// Keeps the tags of an object of the given type that the style allows.
+    pub fn filter_tags<'a, I>(&self, osm_type: OsmType, tags: I) -> HashMap<String, String>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        self.tag_filter(osm_type).filter(tags)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, StyleError> {
+        let mut style = Style {
+            node_tags: TagFilter::default(),
+            way_tags: TagFilter::default(),
+            relation_tags: TagFilter::default(),
+            polygon_keys: HashSet::new(),
+            zordering_tags: Vec::new(),
+        };
+
+        for (i, line) in data.lines().enumerate() {
+            let line_number = i + 1;
+            let err = |msg: String| StyleError::Parse(line_number, msg);
+
+            let fields: Vec<&str> = line
+                .split('#')
+                .next()
+                .unwrap_or("")
+                .split_whitespace()
+                .collect();
+
+            match fields.as_slice() {
+                [] => (),
+                ["zorder", key, value, z_order, flags @ ..] => {
+                    let z_order = z_order
+                        .parse::<i8>()
+                        .map_err(|e| err(format!("invalid z_order {}: {}", z_order, e)))?;
+                    let is_road = match flags {
+                        [] => false,
+                        ["road"] => true,
+                        _ => return Err(err(format!("unknown z_order flags {:?}", flags))),
+                    };
+                    style.zordering_tags.push(ZOrderTag {
+                        key: key.to_string(),
+                        value: if *value == "*" {
+                            None
+                        } else {
+                            Some(value.to_string())
+                        },
+                        z_order,
+                        is_road,
+                    });
+                }
+                [osm_types, key, _data_type, flags @ ..] if flags.len() <= 1 => {
+                    let mut types = Vec::new();
+                    for t in osm_types.split(',') {
+                        match t {
+                            "node" => types.push(OsmType::Node),
+                            // Relations are stored like ways, so way rules
+                            // apply to them too, like in osm2pgsql.
+                            "way" => types.extend(&[OsmType::Way, OsmType::Relation]),
+                            "relation" => types.push(OsmType::Relation),
+                            _ => return Err(err(format!("unknown OsmType {}", t))),
+                        }
+                    }
+
+                    if key.strip_suffix('*').unwrap_or(key).contains('*') {
+                        return Err(err(format!("{} has a wildcard before the end", key)));
+                    }
+
+                    let (mut column, mut polygon, mut linear, mut delete) =
+                        (true, false, false, false);
+                    for flag in flags.iter().flat_map(|f| f.split(',')) {
+                        match flag {
+                            "linear" => linear = true,
+                            "polygon" => polygon = true,
+                            "nocolumn" => column = false,
+                            "delete" => delete = true,
+                            _ => return Err(err(format!("unknown flag {}", flag))),
+                        }
+                    }
+
+                    if polygon && key.ends_with('*') {
+                        return Err(err(format!("{} cannot be a polygon key", key)));
+                    }
+
+                    if polygon && !delete {
+                        style.polygon_keys.insert(key.to_string());
+                    } else if linear {
+                        // A later line overrides an earlier one, so a style
+                        // can be extended to keep a polygon key as a line.
+                        style.polygon_keys.remove(*key);
+                    }
+                    if column || delete {
+                        for t in types {
+                            match t {
+                                OsmType::Node => style.node_tags.add(key, delete),
+                                OsmType::Way => style.way_tags.add(key, delete),
+                                OsmType::Relation => style.relation_tags.add(key, delete),
+                            }
+                        }
+                    }
+                }
+                _ => return Err(err(format!("expected 3 or 4 columns, got {:?}", fields))),
+            }
+        }
+
+        Ok(style)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_prefixes() {
+        let style = Style::parse("node,way name:* text linear\nway name text linear").unwrap();
+        assert!(style.tag_filter(OsmType::Node).keeps("name:de"));
+        assert!(!style.tag_filter(OsmType::Node).keeps("name"));
+        assert!(style.tag_filter(OsmType::Way).keeps("name"));
+        assert!(style.tag_filter(OsmType::Relation).keeps("name:de"));
+    }
+
+    #[test]
+    fn delete_wins_over_keep() {
+        let style =
+            Style::parse("node,way addr:* text linear\nnode addr:interpolation text delete")
+                .unwrap();
+        assert!(style.tag_filter(OsmType::Node).keeps("addr:street"));
+        assert!(!style.tag_filter(OsmType::Node).keeps("addr:interpolation"));
+        assert!(style.tag_filter(OsmType::Way).keeps("addr:interpolation"));
+    }
+
+    #[test]
+    fn polygon_and_linear_flags() {
+        let style = Style::parse(
+            "way building text polygon\nway highway text linear\nway area text polygon,nocolumn",
+        )
+        .unwrap();
+        assert!(style.polygon_keys.contains("building"));
+        assert!(style.polygon_keys.contains("area"));
+        assert!(!style.polygon_keys.contains("highway"));
+        assert!(style.tag_filter(OsmType::Way).keeps("highway"));
+        assert!(!style.tag_filter(OsmType::Way).keeps("area"));
+    }
+
+    #[test]
+    fn linear_overrides_an_earlier_polygon_flag() {
+        let style = Style::parse("way natural text polygon\nway natural text linear").unwrap();
+        assert!(!style.polygon_keys.contains("natural"));
+        assert!(style.tag_filter(OsmType::Way).keeps("natural"));
+    }
+
+    #[test]
+    fn zorder_lines() {
+        let style =
+            Style::parse("zorder highway motorway 38 road\nzorder railway * 5  # any railway")
+                .unwrap();
+        assert_eq!(
+            style.zordering_tags,
+            vec![
+                ZOrderTag {
+                    key: "highway".to_string(),
+                    value: Some("motorway".to_string()),
+                    z_order: 38,
+                    is_road: true,
+                },
+                ZOrderTag {
+                    key: "railway".to_string(),
+                    value: None,
+                    z_order: 5,
+                    is_road: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn multibyte_keys() {
+        let style = Style::parse("node straße text linear\nnode straße* text linear").unwrap();
+        assert!(style.tag_filter(OsmType::Node).keeps("straße"));
+        assert!(style.tag_filter(OsmType::Node).keeps("straßenname"));
+    }
+
+    #[test]
+    fn malformed_lines() {
+        for (data, line) in [
+            ("node name", 1),
+            ("\nnode name text linear polygon", 2),
+            ("area name text linear", 1),
+            ("node na*me text linear", 1),
+            ("node name text sideways", 1),
+            ("way name:* text polygon", 1),
+            ("zorder highway motorway high", 1),
+            ("zorder highway motorway 300", 1),
+            ("zorder highway motorway 3 bridge", 1),
+        ] {
+            match Style::parse(data) {
+                Err(StyleError::Parse(n, _)) => assert_eq!(n, line, "{:?}", data),
+                result => panic!("{:?} parsed as {:?}", data, result),
+            }
+        }
+    }
+
+    #[test]
+    fn default_style_parses() {
+        let style = Style::default();
+        assert!(style.tag_filter(OsmType::Way).keeps("addr:street"));
+        assert!(style.polygon_keys.contains("building"));
+    }
+}
@@ -0,0 +1,101 @@
+use deunicode::deunicode_with_tofu;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Brings a name or query into the form terms are compared in: Unicode
+/// NFKC, so ligatures and full-width letters are plain letters, lowercase,
+/// without accents and transliterated to ASCII, so `Zürich`, `ZURICH` and
+/// `Zurich` or `Москва` and `Moskva` are the same. Characters without a
+/// transliteration are dropped.
+pub fn normalize(text: &str) -> String {
+    let folded = text.nfkc().collect::<String>().to_lowercase();
+    let stripped: String = folded.nfd().filter(|c| !is_combining_mark(*c)).collect();
+    // Transliterations can start with a capital, like `Zh` for `ж`.
+    deunicode_with_tofu(&stripped, "").to_lowercase()
+}
+
+/// Text with something for every step of `normalize` to change: a
+/// ligature, full-width letters, accents precomposed and decomposed, a
+/// capital sharp s and letters of other scripts.
+const PROBE: &str = "ﬁ Ｔｏｋｙｏ Zürich Zu\u{308}rich STRAẞE Łódź Москва Αθήνα 北京市 \u{e000}";
+
+/// What `normalize` makes of a sample text. Saved with the terms of an
+/// index, so an index built by another normalization is noticed, whatever
+/// the binary it comes from.
+pub fn normalization() -> String {
+    normalize(PROBE)
+}
+
+/// Splits a name or query into normalized words.
+pub fn terms(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nfkc() {
+        // Ligatures, full-width letters and superscripts are plain letters.
+        assert_eq!(normalize("ﬁeld"), "field");
+        assert_eq!(normalize("Ｔｏｋｙｏ"), "tokyo");
+        assert_eq!(normalize("m²"), "m2");
+        // Precomposed and decomposed accents are the same.
+        assert_eq!(normalize("Zu\u{308}rich"), normalize("Z\u{fc}rich"));
+    }
+
+    #[test]
+    fn case_folding() {
+        assert_eq!(normalize("ZURICH"), "zurich");
+        assert_eq!(normalize("Straße"), "strasse");
+        assert_eq!(normalize("STRASSE"), "strasse");
+        assert_eq!(normalize("ΑΘΗΝΑ"), normalize("Αθήνα"));
+    }
+
+    #[test]
+    fn accents() {
+        assert_eq!(normalize("Zürich"), "zurich");
+        assert_eq!(normalize("Genève"), "geneve");
+        assert_eq!(normalize("Łódź"), "lodz");
+        assert_eq!(normalize("São Paulo"), "sao paulo");
+        assert_eq!(normalize("Ærøskøbing"), "aeroskobing");
+    }
+
+    #[test]
+    fn transliteration() {
+        assert_eq!(normalize("Москва"), "moskva");
+        assert_eq!(normalize("Αθήνα"), "athena");
+        assert_eq!(normalize("北京市"), "bei jing shi");
+        assert_eq!(normalize("Жуковский"), "zhukovskii");
+        // Characters without a transliteration are dropped.
+        assert_eq!(normalize("a\u{e000}b"), "ab");
+    }
+
+    #[test]
+    fn terms_are_words() {
+        assert_eq!(
+            terms("Café Zürich, 10 Hauptstrasse"),
+            vec!["cafe", "zurich", "10", "hauptstrasse"]
+        );
+        assert_eq!(
+            terms("Saint-Gilles/Sint-Gillis"),
+            vec!["saint", "gilles", "sint", "gillis"]
+        );
+        assert_eq!(terms("北京市"), vec!["bei", "jing", "shi"]);
+        assert!(terms(" , - ").is_empty());
+        assert_eq!(terms("ZURICH"), terms("Zürich"));
+    }
+
+    #[test]
+    fn normalization_covers_every_step() {
+        assert_eq!(
+            normalization(),
+            "fi tokyo zurich zurich strasse lodz moskva athena bei jing shi "
+        );
+    }
+}
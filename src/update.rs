@@ -0,0 +1,423 @@
+use crate::input::{encode_block, Action, Element, ElementReader, InputError};
+use crate::location::FixedCoordinate;
+use crate::node::{process_node, NodeTagsDB};
+use crate::node_store::NodeLocationStore;
+use crate::place::{OsmId, Place};
+use crate::relation::{process_relation, RelationDB};
+use crate::report::{ChangeReport, StoreSizes};
+use crate::reverse_index::ReverseIndex;
+use crate::style::Style;
+use crate::way::{process_way, RoadsDB, WayDB};
+use osm_pbf_iter::{Primitive, PrimitiveBlock};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use std::time::Instant;
+
+/// The stores of an import, which change files are applied to and lookups
+/// read from.
+pub struct Stores<'a> {
+    pub node_store: &'a dyn NodeLocationStore,
+    pub node_tags: &'a NodeTagsDB,
+    pub ways: &'a WayDB,
+    pub roads: &'a RoadsDB,
+    pub relations: &'a RelationDB,
+    pub reverse_index: &'a ReverseIndex,
+}
+
+impl<'a> Stores<'a> {
+    pub fn sizes(&self) -> StoreSizes {
+        StoreSizes {
+            node_coords: self.node_store.len(),
+            node_tags: self.node_tags.len(),
+            ways: self.ways.len(),
+            roads: self.roads.len(),
+            relations: self.relations.len(),
+        }
+    }
+
+    /// The stored object as a `Place`, `None` if it is not stored or is not
+    /// a place.
+    pub fn place(&self, id: OsmId) -> Option<Place> {
+        match id {
+            OsmId::Node(id) => {
+                let tags = self.node_tags.get(&id)?;
+                Place::from_node(id, self.node_store.get(id)?, &tags)
+            }
+            OsmId::Way(id) => Place::from_way(self.ways.get(&id)?.value()),
+            OsmId::Relation(id) => Place::from_relation(self.relations.get(&id)?.value()),
+        }
+    }
+
+    /// Removes a way and everything derived from it.
+    fn remove_way(&self, id: i64) {
+        if let Some((_id, way)) = self.ways.remove(&id) {
+            self.reverse_index.remove_way(id, way.node_ids());
+        }
+        self.roads.remove(&id);
+    }
+
+    fn remove_relation(&self, id: i64) {
+        if let Some((_id, relation)) = self.relations.remove(&id) {
+            self.reverse_index
+                .remove_relation(id, relation.member_ids());
+        }
+    }
+}
+
+/// Changes of one object type, in file order.
+#[derive(Default)]
+struct Changes {
+    nodes: Vec<(Action, Element)>,
+    ways: Vec<(Action, Element)>,
+    relations: Vec<(Action, Element)>,
+}
+
+fn count(report: &mut ChangeReport, action: Action) {
+    match action {
+        Action::Create => report.created += 1,
+        Action::Modify => report.modified += 1,
+        Action::Delete => report.deleted += 1,
+    }
+}
+
+/// Runs the import functions on created and modified objects of one type,
+/// by encoding them into a block like the ones read from a PBF file.
+fn process_batch(
+    batch: &mut Vec<Element>,
+    style: &Style,
+    stores: &Stores,
+    report: &mut ChangeReport,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let data = encode_block(batch);
+    batch.clear();
+    let primitive_block = PrimitiveBlock::parse(&data);
+    for primitive in primitive_block.primitives() {
+        match primitive {
+            Primitive::Node(n) => {
+                if let Err(e) = process_node(&n, stores.node_store, style, stores.node_tags) {
+                    eprintln!("Could not store node {}: {:?}.", n.id as i64, e);
+                    report.error(e.kind());
+                }
+            }
+            Primitive::Way(w) => {
+                let id = w.id as i64;
+                match process_way(&w, style, stores.node_store, stores.roads, stores.ways) {
+                    Ok(()) => stores.reverse_index.add_way(id, w.refs()),
+                    Err(e) => {
+                        eprintln!("Could not process way {}: {:?}.", id, e);
+                        report.error(e.kind());
+                    }
+                }
+            }
+            Primitive::Relation(r) => {
+                stores.reverse_index.add_relation(
+                    r.id as i64,
+                    r.members()
+                        .map(|(_, id, t)| OsmId::from_member(&t, id as i64)),
+                );
+                if let Err(e) = process_relation(&r, style, stores.ways, stores.relations) {
+                    eprintln!(
+                        "Could not assemble the area of relation {}: {:?}.",
+                        r.id as i64, e
+                    );
+                    report.error(e.kind());
+                }
+            }
+        }
+    }
+}
+
+/// Applies the changes of one object type in order. Created and modified
+/// objects replace what was stored for them and are processed in batches,
+/// deletions are applied between batches. `forget` removes what is stored
+/// for an object before it is replaced or deleted.
+fn apply<F>(
+    changes: Vec<(Action, Element)>,
+    style: &Style,
+    stores: &Stores,
+    report: &mut ChangeReport,
+    mut forget: F,
+) where
+    F: FnMut(&Element),
+{
+    let mut batch = Vec::new();
+    for (action, element) in changes {
+        count(report, action);
+        forget(&element);
+        if action == Action::Delete {
+            process_batch(&mut batch, style, stores, report);
+        } else {
+            batch.push(element);
+        }
+    }
+    process_batch(&mut batch, style, stores, report);
+}
+
+/// Applies an osmChange file (`.osc`, `.osc.gz`, `.o5c`) to the stores,
+/// nodes first, then ways and relations, like the import phases. Ways that
+/// are not in the file but use a node that moved get their geometry rebuilt,
+/// and so do the areas of relations containing a moved node or changed way.
+pub fn apply_change<P: AsRef<Path>>(
+    path: P,
+    style: &Style,
+    stores: &Stores,
+) -> Result<ChangeReport, InputError> {
+    let start = Instant::now();
+    let mut report = ChangeReport {
+        file: path.as_ref().display().to_string(),
+        ..ChangeReport::default()
+    };
+
+    let mut changes = Changes::default();
+    for change in ElementReader::open(path)? {
+        let change = change?;
+        match change.1 {
+            Element::Node { .. } => changes.nodes.push(change),
+            Element::Way { .. } => changes.ways.push(change),
+            Element::Relation { .. } => changes.relations.push(change),
+        }
+    }
+
+    let mut moved: HashSet<i64> = HashSet::new();
+    apply(changes.nodes, style, stores, &mut report, |node| {
+        if let Element::Node { id, lat, lon, .. } = node {
+            // An invalid location counts as a move, so the ways using the
+            // node report that it is missing.
+            let location = FixedCoordinate::new(*lat, *lon).ok();
+            if let Some(old) = stores.node_store.get(*id) {
+                if Some(old) != location {
+                    moved.insert(*id);
+                }
+            }
+            stores.node_store.remove(*id);
+            stores.node_tags.remove(id);
+        }
+    });
+
+    let mut changed_ways: HashSet<i64> = HashSet::new();
+    apply(changes.ways, style, stores, &mut report, |way| {
+        changed_ways.insert(way.id());
+        stores.remove_way(way.id());
+    });
+
+    // Way geometries only depend on nodes, so they are rebuilt before the
+    // relations of the file are assembled from them.
+    let invalidated = stores.reverse_index.invalidated_by_nodes(moved);
+    for id in invalidated.ways.iter().copied() {
+        if changed_ways.contains(&id) {
+            continue;
+        }
+        if let Some(mut way) = stores.ways.get_mut(&id) {
+            match way.refresh_coords(stores.node_store) {
+                Ok(()) => {
+                    report.rebuilt_ways += 1;
+                    if stores.roads.contains_key(&id) {
+                        stores.roads.insert(id, way.clone());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Could not rebuild way {}: {:?}.", id, e);
+                    report.error(e.kind());
+                }
+            }
+        }
+    }
+
+    let mut rebuild: BTreeSet<i64> = invalidated.relations;
+    for id in changed_ways {
+        rebuild.extend(stores.reverse_index.invalidated_by_member(OsmId::Way(id)));
+    }
+
+    apply(changes.relations, style, stores, &mut report, |relation| {
+        rebuild.remove(&relation.id());
+        stores.remove_relation(relation.id());
+    });
+
+    for id in rebuild {
+        if let Some(mut relation) = stores.relations.get_mut(&id) {
+            match relation.rebuild_area(stores.ways) {
+                Ok(true) => report.rebuilt_relations += 1,
+                Ok(false) => (),
+                Err(e) => {
+                    eprintln!("Could not rebuild the area of relation {}: {:?}.", id, e);
+                    report.error(e.kind());
+                }
+            }
+        }
+    }
+
+    report.seconds = start.elapsed().as_secs_f64();
+    report.stores = stores.sizes();
+    Ok(report)
+}
+
+/// Stores to run changes and lookups against in tests, filled from
+/// osmChange snippets.
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::node::NodeCoordDB;
+    use crate::spatial::SpatialIndex;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    pub struct Data {
+        node_store: NodeCoordDB,
+        node_tags: NodeTagsDB,
+        ways: WayDB,
+        roads: RoadsDB,
+        relations: RelationDB,
+        reverse_index: ReverseIndex,
+    }
+
+    impl Data {
+        pub fn stores(&self) -> Stores<'_> {
+            Stores {
+                node_store: &self.node_store,
+                node_tags: &self.node_tags,
+                ways: &self.ways,
+                roads: &self.roads,
+                relations: &self.relations,
+                reverse_index: &self.reverse_index,
+            }
+        }
+
+        /// Applies the `create`, `modify` and `delete` blocks in `change`
+        /// with the default style.
+        pub fn apply(&self, change: &str) -> ChangeReport {
+            static FILES: AtomicUsize = AtomicUsize::new(0);
+            let path = env::temp_dir().join(format!(
+                "nominatim_rs-test-{}-{}.osc",
+                process::id(),
+                FILES.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::write(
+                &path,
+                format!("<osmChange version=\"0.6\">{}</osmChange>", change),
+            )
+            .unwrap();
+            let report = apply_change(&path, &Style::default(), &self.stores());
+            fs::remove_file(&path).unwrap();
+            report.unwrap()
+        }
+
+        pub fn spatial_index(&self) -> SpatialIndex {
+            SpatialIndex::build(
+                &self.node_store,
+                &self.node_tags,
+                &self.ways,
+                &self.relations,
+            )
+        }
+
+        pub fn way_coords(&self, id: i64) -> Vec<FixedCoordinate> {
+            let way = self.ways.get(&id).unwrap();
+            way.coords_shape()
+                .coords()
+                .iter()
+                .map(|c| c.location)
+                .collect()
+        }
+
+        /// The outer ring of the first polygon of a relation's area.
+        pub fn area_coords(&self, id: i64) -> Vec<FixedCoordinate> {
+            let relation = self.relations.get(&id).unwrap();
+            relation.area().unwrap().0[0]
+                .outer
+                .coords()
+                .iter()
+                .map(|c| c.location)
+                .collect()
+        }
+    }
+
+    fn at(lat: f64, lon: f64) -> FixedCoordinate {
+        FixedCoordinate::new(lat, lon).unwrap()
+    }
+
+    /// A square of nodes 1 to 4, the closed way 10 around it and the
+    /// multipolygon 20 with 10 as its outer ring.
+    const SQUARE: &str = r#"<create>
+        <node id="1" lat="47.0" lon="8.0"/>
+        <node id="2" lat="47.0" lon="8.1"/>
+        <node id="3" lat="47.1" lon="8.1"/>
+        <node id="4" lat="47.1" lon="8.0"/>
+        <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><nd ref="1"/></way>
+        <relation id="20">
+          <member type="way" ref="10" role="outer"/>
+          <tag k="type" v="multipolygon"/><tag k="landuse" v="forest"/>
+        </relation>
+    </create>"#;
+
+    #[test]
+    fn nodes_are_created_modified_and_deleted() {
+        let data = Data::default();
+        let report = data.apply(
+            r#"<create>
+                <node id="1" lat="47.0" lon="8.0"><tag k="name" v="Eins"/></node>
+                <node id="2" lat="47.0" lon="8.1"/>
+            </create>"#,
+        );
+        assert_eq!((report.created, report.modified, report.deleted), (2, 0, 0));
+        let stores = data.stores();
+        assert_eq!(stores.node_store.get(1), Some(at(47.0, 8.0)));
+        assert_eq!(data.node_tags.get(&1).unwrap()["name"], "Eins");
+
+        // The new version has no tags left.
+        let report = data.apply(r#"<modify><node id="1" lat="47.5" lon="8.5"/></modify>"#);
+        assert_eq!((report.created, report.modified, report.deleted), (0, 1, 0));
+        assert_eq!(stores.node_store.get(1), Some(at(47.5, 8.5)));
+        assert!(data.node_tags.get(&1).is_none());
+
+        let report = data.apply(r#"<delete><node id="2"/></delete>"#);
+        assert_eq!((report.created, report.modified, report.deleted), (0, 0, 1));
+        assert_eq!(stores.node_store.get(2), None);
+        assert_eq!(report.stores.node_coords, 1);
+    }
+
+    #[test]
+    fn moved_nodes_rebuild_ways_and_areas() {
+        let data = Data::default();
+        data.apply(SQUARE);
+        assert_eq!(data.area_coords(20)[2], at(47.1, 8.1));
+
+        let report = data.apply(r#"<modify><node id="3" lat="47.2" lon="8.2"/></modify>"#);
+        assert_eq!((report.rebuilt_ways, report.rebuilt_relations), (1, 1));
+        assert_eq!(data.way_coords(10)[2], at(47.2, 8.2));
+        assert_eq!(data.area_coords(20)[2], at(47.2, 8.2));
+
+        // A node that did not move rebuilds nothing.
+        let report = data.apply(r#"<modify><node id="3" lat="47.2" lon="8.2"/></modify>"#);
+        assert_eq!((report.rebuilt_ways, report.rebuilt_relations), (0, 0));
+    }
+
+    #[test]
+    fn a_node_rebuilds_every_way_and_relation_using_it() {
+        let data = Data::default();
+        data.apply(SQUARE);
+        // Node 3 is also on a street that is in no relation.
+        data.apply(
+            r#"<create>
+                <node id="5" lat="47.2" lon="8.1"/>
+                <way id="11"><nd ref="3"/><nd ref="5"/><tag k="highway" v="primary"/></way>
+            </create>"#,
+        );
+        assert_eq!(data.reverse_index.ways_of_node(3), vec![10, 11]);
+
+        let report = data.apply(r#"<modify><node id="3" lat="47.15" lon="8.15"/></modify>"#);
+        assert_eq!((report.rebuilt_ways, report.rebuilt_relations), (2, 1));
+        assert_eq!(data.way_coords(10)[2], at(47.15, 8.15));
+        assert_eq!(data.way_coords(11)[0], at(47.15, 8.15));
+        assert_eq!(
+            data.roads.get(&11).unwrap().coords_shape().coords()[0].location,
+            at(47.15, 8.15)
+        );
+        assert_eq!(data.area_coords(20)[2], at(47.15, 8.15));
+    }
+}
@@ -1,43 +1,13 @@
-use crate::node::{Coordinate, NodeCoordDB, NodeTags};
+use crate::node::{Coordinate, NodeTags};
+use crate::node_store::NodeLocationStore;
+use crate::style::{OsmType, Style, ZOrderTag};
 use dashmap::DashMap;
 use osm_pbf_iter::Way;
-use std::collections::{HashMap, HashSet};
-use std::iter::FromIterator;
-
-// Array used to specify z_order per key/value combination.
-// Each element has the form {key, value, z_order, is_road}.
-// If is_road=1, the object will be added to planet_osm_roads.
-lazy_static! {
-    pub static ref ZORDERING_TAGS: HashSet<(&'static str, &'static str, i8, bool)> =
-        HashSet::from_iter(vec![
-            ("railway", "nil", 5, true),
-            ("boundary", "administrative", 0, true),
-            ("bridge", "yes", 10, false),
-            ("bridge", "true", 10, false),
-            ("bridge", "1", 10, false),
-            ("tunnel", "yes", -10, false),
-            ("tunnel", "true", -10, false),
-            ("tunnel", "1", -10, false),
-            ("highway", "minor", 3, false),
-            ("highway", "road", 3, false),
-            ("highway", "unclassified", 3, false),
-            ("highway", "residential", 3, false),
-            ("highway", "tertiary_link", 4, false),
-            ("highway", "tertiary", 4, false),
-            ("highway", "secondary_link", 6, true),
-            ("highway", "secondary", 6, true),
-            ("highway", "primary_link", 7, true),
-            ("highway", "primary", 7, true),
-            ("highway", "trunk_link", 8, true),
-            ("highway", "trunk", 8, true),
-            ("highway", "motorway_link", 9, true),
-            ("highway", "motorway", 9, true),
-        ]);
-}
-
-pub type RoadsDB = DashMap<u64, DebugWay>;
-
-pub type WayDB = DashMap<u64, DebugWay>;
+use std::collections::HashMap;
+
+pub type RoadsDB = DashMap<i64, DebugWay>;
+
+pub type WayDB = DashMap<i64, DebugWay>;
 
 #[derive(Debug, PartialOrd, PartialEq, Clone)]
 pub struct LineString {
@@ -46,19 +16,28 @@ pub struct LineString {
 
 #[derive(Debug, PartialOrd, PartialEq, Clone)]
 pub enum WayProcessingError {
-    LineStringCreationError(u64),
+    LineStringCreationError(i64),
     ClosedLineStringCreationError(&'static str),
 }
 
+impl WayProcessingError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WayProcessingError::LineStringCreationError(_) => "LineStringCreationError",
+            WayProcessingError::ClosedLineStringCreationError(_) => "ClosedLineStringCreationError",
+        }
+    }
+}
+
 impl LineString {
-    pub fn from_node_refs(
+    pub fn from_node_refs<S: NodeLocationStore + ?Sized>(
         way: &Way,
-        node_coords_db: &NodeCoordDB,
+        node_store: &S,
     ) -> Result<LineString, WayProcessingError> {
         let mut line: Vec<Coordinate> = Vec::new();
-        for node_id in way.refs().map(|i| i as u64) {
-            if let Some(coord) = node_coords_db.get(&node_id) {
-                line.push(Coordinate::from_db_coord(node_id, *coord.value()));
+        for node_id in way.refs() {
+            if let Some(coord) = node_store.get(node_id) {
+                line.push(Coordinate::new(node_id, coord));
             } else {
                 return Err(WayProcessingError::LineStringCreationError(node_id));
             }
@@ -78,13 +57,31 @@ impl ClosedLineString {
             Err(WayProcessingError::ClosedLineStringCreationError("Cannot create a ClosedLineString from fewer than 3 coordinates. Try creating a Line instead."))
         } else {
             if coords.first().unwrap() == coords.last().unwrap() {
-                Ok(ClosedLineString { coords: coords })
+                Ok(ClosedLineString { coords })
             } else {
-                coords.push(*(coords.first().clone().unwrap()));
-                Ok(ClosedLineString { coords: coords })
+                coords.push(*(coords.first().unwrap()));
+                Ok(ClosedLineString { coords })
             }
         }
     }
+
+    pub fn coords(&self) -> &[Coordinate] {
+        &self.coords
+    }
+
+    /// Even-odd ray casting test, with `lon` as x and `lat` as y.
+    pub fn contains(&self, point: &Coordinate) -> bool {
+        let (x, y) = (point.location.lon as f64, point.location.lat as f64);
+        let mut inside = false;
+        for edge in self.coords.windows(2) {
+            let (ax, ay) = (edge[0].location.lon as f64, edge[0].location.lat as f64);
+            let (bx, by) = (edge[1].location.lon as f64, edge[1].location.lat as f64);
+            if (ay > y) != (by > y) && x < (bx - ax) * (y - ay) / (by - ay) + ax {
+                inside = !inside;
+            }
+        }
+        inside
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -93,42 +90,141 @@ pub enum CoordsShape {
     Polygonal(ClosedLineString),
 }
 
+impl CoordsShape {
+    pub fn coords(&self) -> &[Coordinate] {
+        match self {
+            CoordsShape::Linear(ls) => &ls.coords,
+            CoordsShape::Polygonal(cls) => cls.coords(),
+        }
+    }
+}
+
+/// A single outer ring and the inner rings (holes) it contains.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Polygon {
+    pub outer: ClosedLineString,
+    pub inners: Vec<ClosedLineString>,
+}
+
+/// The assembled geometry of a multipolygon or boundary relation.
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
-pub struct Area(ClosedLineString);
+pub struct Area(pub Vec<Polygon>);
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct DebugWay {
-    id: u64,
+    id: i64,
+    /// Holds every node of the way in file order, with its id, so the
+    /// geometry can be rebuilt and the nodes found without the original file.
     coords_shape: CoordsShape,
+    z_order: i32,
     tags: Option<HashMap<String, String>>,
 }
 
+impl DebugWay {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn tags(&self) -> Option<&NodeTags> {
+        self.tags.as_ref()
+    }
+
+    pub fn coords_shape(&self) -> &CoordsShape {
+        &self.coords_shape
+    }
+
+    pub fn z_order(&self) -> i32 {
+        self.z_order
+    }
+
+    /// The ids of the way's nodes, in order.
+    pub fn node_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.coords_shape.coords().iter().map(|c| c.id)
+    }
+
+    /// Looks up the locations of the way's nodes again, after some of them
+    /// moved. The way is left unchanged if a node is missing.
+    pub fn refresh_coords<S: NodeLocationStore + ?Sized>(
+        &mut self,
+        node_store: &S,
+    ) -> Result<(), WayProcessingError> {
+        let mut coords = Vec::with_capacity(self.coords_shape.coords().len());
+        for node_id in self.node_ids() {
+            match node_store.get(node_id) {
+                Some(coord) => coords.push(Coordinate::new(node_id, coord)),
+                None => return Err(WayProcessingError::LineStringCreationError(node_id)),
+            }
+        }
+        self.coords_shape = match self.coords_shape {
+            CoordsShape::Linear(_) => CoordsShape::Linear(LineString { coords }),
+            CoordsShape::Polygonal(_) => CoordsShape::Polygonal(ClosedLineString::new(coords)?),
+        };
+        Ok(())
+    }
+}
+
 impl PartialOrd for DebugWay {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.id.partial_cmp(&other.id)
     }
 }
 
-pub fn add_z_order(tags: &NodeTags) -> (Option<i8>, bool) {
+/// Tags that never make a way an area, unless `area=yes` says so. Closed
+/// coastline ways are islands, but osm2pgsql keeps them as lines.
+const NON_AREA_TAGS: [(&str, &str); 1] = [("natural", "coastline")];
+
+/// Whether a way is an area, with osm2pgsql semantics. Only closed ways can
+/// be areas. `area=yes` and `area=no` override everything else, then
+/// `NON_AREA_TAGS` keep a way a line, otherwise any key in the style's `polygon_keys` makes the way an area, so which
+/// keys do is up to the style's `polygon` and `linear` flags. The tags are
+/// checked before filtering, so `nocolumn` polygon keys are seen.
+pub fn is_area(way: &Way, style: &Style) -> bool {
+    let refs: Vec<i64> = way.refs().collect();
+    if refs.len() < 4 || refs.first() != refs.last() {
+        return false;
+    }
+
+    let tags: Vec<(&str, &str)> = way.tags().collect();
+    match tags.iter().find(|(k, _v)| *k == "area").map(|(_k, v)| *v) {
+        Some("yes") | Some("1") | Some("true") => return true,
+        Some("no") | Some("0") | Some("false") => return false,
+        _ => (),
+    }
+
+    if tags.iter().any(|tag| NON_AREA_TAGS.contains(tag)) {
+        return false;
+    }
+
+    tags.iter().any(|(k, _v)| style.polygon_keys.contains(*k))
+}
+
+/// Computes the z_order of a way and whether it belongs in the `ROADS_DB`,
+/// like the `add_z_order` function of the osm2pgsql Lua style: ten times the
+/// numeric `layer`, plus the z_order of every matching `zordering_tags`
+/// entry. An entry with a value matches only that value, one without a value
+/// matches any value of the key.
+pub fn add_z_order(tags: &NodeTags, zordering_tags: &[ZOrderTag]) -> (i32, bool) {
     // The default z_order is 0
-    let mut z_order: Option<i8> = None;
+    let mut z_order: i32 = 0;
     let mut is_road: bool = false;
 
-    if let Some(l) = tags.get("layer") {
-        if let Ok(n) = l.parse::<i8>() {
-            z_order = Some(10 * n);
+    if let Some(n) = tags.get("layer").and_then(|l| l.trim().parse::<f64>().ok()) {
+        if n.is_finite() {
+            z_order = (10.0 * n) as i32;
         }
     }
 
-    for (k, v, z, r) in ZORDERING_TAGS.iter() {
-        if (v != &"nil" && tags.get(*k).is_some()) || (v == &"nil" && tags.get(*k).is_some()) {
-            if *r {
+    for tag in zordering_tags.iter() {
+        let matches = match (&tag.value, tags.get(&tag.key)) {
+            (Some(value), Some(v)) => value == v,
+            (None, Some(_)) => true,
+            (_, None) => false,
+        };
+        if matches {
+            if tag.is_road {
                 is_road = true;
             }
-            match z_order {
-                Some(n) => z_order = Some(n + *z),
-                None => z_order = Some(*z),
-            }
+            z_order += i32::from(tag.z_order);
         }
     }
 
@@ -137,73 +233,22 @@ pub fn add_z_order(tags: &NodeTags) -> (Option<i8>, bool) {
 
 pub fn process_way<'a>(
     way: &'a Way<'a>,
-    generic_keys: &HashSet<&'static str>,
-    node_coord_db: &NodeCoordDB,
+    style: &Style,
+    node_store: &dyn NodeLocationStore,
     roads_db: &RoadsDB,
     way_db: &WayDB,
 ) -> Result<(), WayProcessingError> {
-    let k = way.id;
-
-    let mut tags: NodeTags = way
-        .tags()
-        .map(|(a, b)| (String::from(a), String::from(b)))
-        .collect();
-
-    let mut final_tags: Option<NodeTags> = None;
-    let mut is_polygon = false;
-
-    if tags.len() > 0 {
-        let filtered_tag_keys: Vec<String> =
-            HashSet::from_iter(way.tags().map(|(k, _v)| k).collect::<Vec<&str>>())
-                .intersection(generic_keys)
-                .cloned()
-                .map(|s| String::from(s))
-                .collect();
-
-        if !filtered_tag_keys.is_empty() {
-            for k in tags.keys().cloned().collect::<Vec<String>>() {
-                if !filtered_tag_keys.contains(&k) {
-                    tags.remove(&k);
-                }
-            }
-        }
-    }
+    let k = way.id as i64;
 
-    let (z_order, is_road) = add_z_order(&tags);
+    let tags: NodeTags = style.filter_tags(OsmType::Way, way.tags());
 
-    if let Some(n) = z_order {
-        tags.insert("z_order".to_string(), n.to_string());
-    }
+    let is_polygon = is_area(way, style);
 
-    if tags.len() > 0 {
-        let filtered_polygon_keys: Vec<&str> =
-            HashSet::from_iter(way.tags().map(|(k, _v)| k).collect::<Vec<&str>>())
-                .intersection(generic_keys)
-                .cloned()
-                .collect();
+    let (z_order, is_road) = add_z_order(&tags, &style.zordering_tags);
 
-        if filtered_polygon_keys.len() > 0 {
-            is_polygon = true;
-        }
+    let final_tags = if tags.is_empty() { None } else { Some(tags) };
 
-        if let Some(v) = tags.get("area") {
-            for x in ["yes", "1", "true"].iter() {
-                if *x == v.as_str() {
-                    is_polygon = true;
-                }
-            }
-
-            for x in ["no", "0", "false"].iter() {
-                if *x == v.as_str() {
-                    is_polygon = false;
-                }
-            }
-        }
-
-        final_tags = Some(tags);
-    }
-
-    match LineString::from_node_refs(way, node_coord_db) {
+    match LineString::from_node_refs(way, node_store) {
         Ok(ls) => {
             let w: DebugWay;
 
@@ -211,8 +256,9 @@ pub fn process_way<'a>(
                 match ClosedLineString::new(ls.coords) {
                     Ok(cls) => {
                         w = DebugWay {
-                            id: way.id,
+                            id: k,
                             coords_shape: CoordsShape::Polygonal(cls),
+                            z_order,
                             tags: final_tags,
                         };
                     }
@@ -222,8 +268,9 @@ pub fn process_way<'a>(
                 }
             } else {
                 w = DebugWay {
-                    id: way.id,
+                    id: k,
                     coords_shape: CoordsShape::Linear(ls),
+                    z_order,
                     tags: final_tags,
                 };
             }
@@ -238,3 +285,143 @@ pub fn process_way<'a>(
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{encode_block, Element};
+    use crate::location::FixedCoordinate;
+    use crate::node::NodeCoordDB;
+    use osm_pbf_iter::{Primitive, PrimitiveBlock};
+
+    fn node_store() -> NodeCoordDB {
+        let store = NodeCoordDB::default();
+        for (id, lat, lon) in [(1, 47.0, 8.0), (2, 47.0, 8.1), (3, 47.1, 8.1)] {
+            NodeLocationStore::insert(&store, id, FixedCoordinate::new(lat, lon).unwrap()).unwrap();
+        }
+        store
+    }
+
+    fn process(refs: Vec<i64>, tags: &[(&str, &str)], node_store: &NodeCoordDB) -> DebugWay {
+        let data = encode_block(&[Element::Way {
+            id: 10,
+            refs,
+            info: Default::default(),
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }]);
+        let block = PrimitiveBlock::parse(&data);
+        let way_db = WayDB::default();
+        match block.primitives().next() {
+            Some(Primitive::Way(way)) => process_way(
+                &way,
+                &Style::default(),
+                node_store,
+                &RoadsDB::default(),
+                &way_db,
+            )
+            .unwrap(),
+            p => panic!("unexpected {:?}", p),
+        }
+        let way = way_db.get(&10).unwrap().clone();
+        way
+    }
+
+    #[test]
+    fn node_ids_come_from_the_geometry() {
+        let store = node_store();
+        let line = process(vec![1, 2, 3], &[("highway", "primary")], &store);
+        assert!(matches!(line.coords_shape(), CoordsShape::Linear(_)));
+        assert_eq!(line.node_ids().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let mut area = process(vec![1, 2, 3, 1], &[("building", "yes")], &store);
+        assert!(matches!(area.coords_shape(), CoordsShape::Polygonal(_)));
+        assert_eq!(area.node_ids().collect::<Vec<_>>(), vec![1, 2, 3, 1]);
+
+        NodeLocationStore::insert(&store, 2, FixedCoordinate::new(47.0, 8.2).unwrap()).unwrap();
+        area.refresh_coords(&store).unwrap();
+        assert_eq!(area.node_ids().collect::<Vec<_>>(), vec![1, 2, 3, 1]);
+        assert_eq!(
+            area.coords_shape().coords()[1].location,
+            FixedCoordinate::new(47.0, 8.2).unwrap()
+        );
+    }
+
+    #[test]
+    fn closed_coastlines_are_not_areas() {
+        let store = node_store();
+        let coast = process(vec![1, 2, 3, 1], &[("natural", "coastline")], &store);
+        assert!(matches!(coast.coords_shape(), CoordsShape::Linear(_)));
+
+        let wood = process(vec![1, 2, 3, 1], &[("natural", "wood")], &store);
+        assert!(matches!(wood.coords_shape(), CoordsShape::Polygonal(_)));
+
+        let island = process(
+            vec![1, 2, 3, 1],
+            &[("natural", "coastline"), ("area", "yes")],
+            &store,
+        );
+        assert!(matches!(island.coords_shape(), CoordsShape::Polygonal(_)));
+    }
+
+    fn z_order(tags: &[(&str, &str)]) -> (i32, bool) {
+        let tags: NodeTags = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        add_z_order(&tags, &Style::default().zordering_tags)
+    }
+
+    /// The values osm2pgsql's `add_z_order` gives with its default style.
+    #[test]
+    fn z_order_matches_osm2pgsql() {
+        assert_eq!(z_order(&[("highway", "motorway")]), (9, true));
+        assert_eq!(z_order(&[("highway", "motorway_link")]), (9, true));
+        assert_eq!(z_order(&[("highway", "secondary")]), (6, true));
+        assert_eq!(z_order(&[("highway", "residential")]), (3, false));
+        assert_eq!(z_order(&[("railway", "rail")]), (5, true));
+        assert_eq!(
+            z_order(&[("railway", "tram"), ("highway", "primary")]),
+            (12, true)
+        );
+        assert_eq!(z_order(&[("boundary", "administrative")]), (0, true));
+    }
+
+    #[test]
+    fn z_order_adds_bridges_tunnels_and_layers() {
+        assert_eq!(
+            z_order(&[("highway", "primary"), ("bridge", "yes"), ("layer", "1")]),
+            (27, true)
+        );
+        assert_eq!(
+            z_order(&[
+                ("highway", "residential"),
+                ("tunnel", "yes"),
+                ("layer", "-1")
+            ]),
+            (-17, false)
+        );
+        assert_eq!(
+            z_order(&[("railway", "subway"), ("tunnel", "true")]),
+            (-5, true)
+        );
+        assert_eq!(z_order(&[("bridge", "yes")]), (10, false));
+        assert_eq!(z_order(&[("layer", "-2")]), (-20, false));
+        // Values the style does not know count for nothing.
+        assert_eq!(z_order(&[("highway", "footway")]), (0, false));
+        assert_eq!(
+            z_order(&[("highway", "footway"), ("bridge", "no")]),
+            (0, false)
+        );
+        assert_eq!(
+            z_order(&[("highway", "primary"), ("layer", "high")]),
+            (7, true)
+        );
+        assert_eq!(
+            z_order(&[("highway", "primary"), ("layer", "inf")]),
+            (7, true)
+        );
+    }
+}